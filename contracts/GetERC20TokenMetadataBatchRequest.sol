@@ -0,0 +1,70 @@
+//SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+/**
+ @dev This contract is not meant to be deployed. Instead, use a static call with the
+      deployment bytecode as payload.
+ */
+contract GetERC20TokenMetadataBatchRequest {
+    // Return data of calls returning more than this is dropped, guarding against tokens
+    // returning huge strings
+    uint256 internal constant MAX_RETURN_DATA_SIZE = 1024;
+
+    // The raw return data of every call is returned, empty when the call failed. Decoding is
+    // left to the caller as tokens such as MKR return bytes32 instead of string symbols.
+    struct TokenMetadata {
+        bool hasCode;
+        bytes symbol;
+        bytes name;
+        bytes decimals;
+        bytes totalSupply;
+    }
+
+    constructor(address[] memory tokens) {
+        TokenMetadata[] memory allTokenMetadata = new TokenMetadata[](
+            tokens.length
+        );
+
+        for (uint256 i = 0; i < tokens.length; ++i) {
+            address token = tokens[i];
+
+            if (token.code.length == 0) {
+                continue;
+            }
+
+            TokenMetadata memory tokenMetadata;
+            tokenMetadata.hasCode = true;
+            tokenMetadata.symbol = tryStaticCall(token, "symbol()");
+            tokenMetadata.name = tryStaticCall(token, "name()");
+            tokenMetadata.decimals = tryStaticCall(token, "decimals()");
+            tokenMetadata.totalSupply = tryStaticCall(token, "totalSupply()");
+
+            allTokenMetadata[i] = tokenMetadata;
+        }
+
+        // ensure abi encoding, not needed here but increase reusability for different return types
+        // note: abi.encode add a first 32 bytes word with the address of the original data
+        bytes memory _abiEncodedData = abi.encode(allTokenMetadata);
+
+        assembly {
+            // Return from the start of the data (discarding the original data address)
+            // up to the end of the memory used
+            let dataStart := add(_abiEncodedData, 0x20)
+            return(dataStart, sub(msize(), dataStart))
+        }
+    }
+
+    function tryStaticCall(
+        address token,
+        string memory signature
+    ) internal view returns (bytes memory) {
+        (bool success, bytes memory data) = token.staticcall(
+            abi.encodeWithSignature(signature)
+        );
+
+        if (!success || data.length > MAX_RETURN_DATA_SIZE) {
+            return "";
+        }
+        return data;
+    }
+}
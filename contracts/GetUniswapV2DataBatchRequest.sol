@@ -21,6 +21,20 @@ interface IERC20 {
       deployment bytecode as payload.
  */
 contract GetUniswapV2PoolDataBatchRequest {
+    // Status codes returned for every pool, anything other than OK means the entry is rejected
+    uint8 internal constant OK = 0;
+    uint8 internal constant POOL_CODE_MISSING = 1;
+    uint8 internal constant TOKEN_A_CODE_MISSING = 2;
+    uint8 internal constant TOKEN_B_CODE_MISSING = 3;
+    uint8 internal constant TOKEN_A_DECIMALS_CALL_FAILED = 4;
+    uint8 internal constant TOKEN_B_DECIMALS_CALL_FAILED = 5;
+    uint8 internal constant TOKEN_A_INVALID_DECIMALS = 6;
+    uint8 internal constant TOKEN_B_INVALID_DECIMALS = 7;
+
+    // Status codes of getTokenDecimals, mapped to the token A/B specific codes above
+    uint8 internal constant DECIMALS_CALL_FAILED = 1;
+    uint8 internal constant INVALID_DECIMALS = 2;
+
     struct PoolData {
         address tokenA;
         uint8 tokenADecimals;
@@ -28,6 +42,8 @@ contract GetUniswapV2PoolDataBatchRequest {
         uint8 tokenBDecimals;
         uint112 reserve0;
         uint112 reserve1;
+        uint32 blockTimestampLast;
+        uint8 status;
     }
 
     constructor(address[] memory pools) {
@@ -36,7 +52,10 @@ contract GetUniswapV2PoolDataBatchRequest {
         for (uint256 i = 0; i < pools.length; ++i) {
             address poolAddress = pools[i];
 
-            if (codeSizeIsZero(poolAddress)) continue;
+            if (codeSizeIsZero(poolAddress)) {
+                allPoolData[i].status = POOL_CODE_MISSING;
+                continue;
+            }
 
             PoolData memory poolData;
             //Get tokens A and B
@@ -44,67 +63,48 @@ contract GetUniswapV2PoolDataBatchRequest {
             poolData.tokenB = IUniswapV2Pair(poolAddress).token1();
 
             //Check that tokenA and tokenB do not have codesize of 0
-            if (codeSizeIsZero(poolData.tokenA)) continue;
-            if (codeSizeIsZero(poolData.tokenB)) continue;
+            if (codeSizeIsZero(poolData.tokenA)) {
+                poolData.status = TOKEN_A_CODE_MISSING;
+            } else if (codeSizeIsZero(poolData.tokenB)) {
+                poolData.status = TOKEN_B_CODE_MISSING;
+            }
 
             //Get tokenA decimals
-            (
-                bool tokenADecimalsSuccess,
-                bytes memory tokenADecimalsData
-            ) = poolData.tokenA.call(abi.encodeWithSignature("decimals()"));
-
-            if (tokenADecimalsSuccess) {
-                uint256 tokenADecimals;
-
-                if (tokenADecimalsData.length == 32) {
-                    (tokenADecimals) = abi.decode(
-                        tokenADecimalsData,
-                        (uint256)
-                    );
-
-                    if (tokenADecimals == 0 || tokenADecimals > 255) {
-                        continue;
-                    } else {
-                        poolData.tokenADecimals = uint8(tokenADecimals);
-                    }
-                } else {
-                    continue;
+            if (poolData.status == OK) {
+                uint8 tokenAStatus;
+                (poolData.tokenADecimals, tokenAStatus) = getTokenDecimals(
+                    poolData.tokenA
+                );
+
+                if (tokenAStatus == DECIMALS_CALL_FAILED) {
+                    poolData.status = TOKEN_A_DECIMALS_CALL_FAILED;
+                } else if (tokenAStatus == INVALID_DECIMALS) {
+                    poolData.status = TOKEN_A_INVALID_DECIMALS;
                 }
-            } else {
-                continue;
             }
-            //Get tokenB decimals
 
-            (
-                bool tokenBDecimalsSuccess,
-                bytes memory tokenBDecimalsData
-            ) = poolData.tokenB.call(abi.encodeWithSignature("decimals()"));
-
-            if (tokenBDecimalsSuccess) {
-                uint256 tokenBDecimals;
-
-                if (tokenBDecimalsData.length == 32) {
-                    (tokenBDecimals) = abi.decode(
-                        tokenBDecimalsData,
-                        (uint256)
-                    );
-
-                    if (tokenBDecimals == 0 || tokenBDecimals > 255) {
-                        continue;
-                    } else {
-                        poolData.tokenBDecimals = uint8(tokenBDecimals);
-                    }
-                } else {
-                    continue;
+            //Get tokenB decimals
+            if (poolData.status == OK) {
+                uint8 tokenBStatus;
+                (poolData.tokenBDecimals, tokenBStatus) = getTokenDecimals(
+                    poolData.tokenB
+                );
+
+                if (tokenBStatus == DECIMALS_CALL_FAILED) {
+                    poolData.status = TOKEN_B_DECIMALS_CALL_FAILED;
+                } else if (tokenBStatus == INVALID_DECIMALS) {
+                    poolData.status = TOKEN_B_INVALID_DECIMALS;
                 }
-            } else {
-                continue;
             }
 
             // Get reserves
-            (poolData.reserve0, poolData.reserve1, ) = IUniswapV2Pair(
-                poolAddress
-            ).getReserves();
+            if (poolData.status == OK) {
+                (
+                    poolData.reserve0,
+                    poolData.reserve1,
+                    poolData.blockTimestampLast
+                ) = IUniswapV2Pair(poolAddress).getReserves();
+            }
 
             allPoolData[i] = poolData;
         }
@@ -121,6 +121,25 @@ contract GetUniswapV2PoolDataBatchRequest {
         }
     }
 
+    function getTokenDecimals(
+        address token
+    ) internal returns (uint8, uint8) {
+        (bool tokenDecimalsSuccess, bytes memory tokenDecimalsData) = token
+            .call(abi.encodeWithSignature("decimals()"));
+
+        if (!tokenDecimalsSuccess || tokenDecimalsData.length != 32) {
+            return (0, DECIMALS_CALL_FAILED);
+        }
+
+        uint256 tokenDecimals = abi.decode(tokenDecimalsData, (uint256));
+
+        if (tokenDecimals == 0 || tokenDecimals > 255) {
+            return (0, INVALID_DECIMALS);
+        }
+
+        return (uint8(tokenDecimals), OK);
+    }
+
     function codeSizeIsZero(address target) internal view returns (bool) {
         if (target.code.length == 0) {
             return true;
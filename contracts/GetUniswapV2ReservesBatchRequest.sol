@@ -0,0 +1,74 @@
+//SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+interface IUniswapV2Pair {
+    function getReserves()
+        external
+        view
+        returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+}
+
+/**
+ @dev This contract is not meant to be deployed. Instead, use a static call with the
+      deployment bytecode as payload.
+ */
+contract GetUniswapV2ReservesBatchRequest {
+    struct Reserves {
+        uint112 reserve0;
+        uint112 reserve1;
+        uint32 blockTimestampLast;
+        // False when the pool has no code, getReserves reverted or returned invalid reserves
+        bool success;
+    }
+
+    constructor(address[] memory pools) {
+        Reserves[] memory allReserves = new Reserves[](pools.length);
+
+        for (uint256 i = 0; i < pools.length; ++i) {
+            address poolAddress = pools[i];
+
+            if (poolAddress.code.length == 0) {
+                continue;
+            }
+
+            // A low level call, so that short return data or reserves out of range skip the
+            // pool like a reverting getReserves instead of reverting the whole batch
+            (bool success, bytes memory data) = poolAddress.staticcall(
+                abi.encodeWithSelector(IUniswapV2Pair.getReserves.selector)
+            );
+            if (!success || data.length < 96) {
+                continue;
+            }
+
+            (uint256 reserve0, uint256 reserve1, uint256 blockTimestampLast) = abi.decode(
+                data,
+                (uint256, uint256, uint256)
+            );
+            if (
+                reserve0 > type(uint112).max ||
+                reserve1 > type(uint112).max ||
+                blockTimestampLast > type(uint32).max
+            ) {
+                continue;
+            }
+
+            allReserves[i] = Reserves(
+                uint112(reserve0),
+                uint112(reserve1),
+                uint32(blockTimestampLast),
+                true
+            );
+        }
+
+        // ensure abi encoding, not needed here but increase reusability for different return types
+        // note: abi.encode add a first 32 bytes word with the address of the original data
+        bytes memory _abiEncodedData = abi.encode(allReserves);
+
+        assembly {
+            // Return from the start of the data (discarding the original data address)
+            // up to the end of the memory used
+            let dataStart := add(_abiEncodedData, 0x20)
+            return(dataStart, sub(msize(), dataStart))
+        }
+    }
+}
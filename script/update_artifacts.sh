@@ -0,0 +1,20 @@
+#!/usr/bin/env bash
+# Builds the batch request contracts and copies their forge artifacts to src/contracts, where
+# abigen reads the ABI and creation bytecode. Run it after changing anything in contracts/.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+forge build
+
+# Source file and contract name of each artifact
+artifacts=(
+    "GetERC20TokenMetadataBatchRequest.sol GetERC20TokenMetadataBatchRequest"
+    "GetUniswapV2DataBatchRequest.sol GetUniswapV2PoolDataBatchRequest"
+    "GetUniswapV2PairsBatchRequest.sol GetUniswapV2PairsBatchRequest"
+    "GetUniswapV2ReservesBatchRequest.sol GetUniswapV2ReservesBatchRequest"
+    "GetWethValueInPoolBatchRequest.sol GetWethValueInPoolBatchRequest"
+)
+for artifact in "${artifacts[@]}"; do
+    read -r source contract <<< "$artifact"
+    cp "out/$source/$contract.json" "src/contracts/$contract.json"
+done
@@ -0,0 +1,831 @@
+//! Deployless batch requests.
+//!
+//! A batch request is a contract whose constructor does all the work and returns the result
+//! instead of the runtime code. Running the creation bytecode through `eth_call` gives many view
+//! calls in one round trip without deploying anything. [`BatchRequest`] describes one such
+//! contract and [`execute_batch_request`] takes care of chunking, concurrency, retries, progress
+//! reporting and merging the results back onto the inputs.
+//!
+//! Chunks that keep failing, usually because a single input runs the call out of gas, are bisected
+//! until the failing inputs are isolated. The largest chunk size that worked after a bisection is
+//! remembered per request and used as the step of later calls, each later call that succeeds at
+//! that size grows it back by one input. Remembered sizes are forgotten with
+//! [`reset_batch_sizes`] or when setting new [`BatchLimits`].
+//!
+//! When [`BatchLimits`] are set and no step is given, the first chunk of a request is measured and
+//! the chunk size is tuned to the largest one staying under the gas and response size limits of
+//! the node.
+//!
+//! Requests can also implement [`BatchRequest::multicall`], fetching the same outputs through
+//! Multicall3 for nodes that reject deployless calls, see [`BatchBackend`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ethers::{
+    abi::{Abi, ParamType, Token},
+    prelude::ContractFactory,
+    providers::{Middleware, MiddlewareError, RawCall, RpcError},
+    types::{spoof, BlockId, BlockNumber, Bytes, U256},
+};
+use futures::{stream, StreamExt};
+use indicatif::ProgressBar;
+use lazy_static::lazy_static;
+
+use crate::errors::{AMMError, BatchDecodeError};
+
+/// A deployless batch contract returning one array element per input.
+#[async_trait]
+pub trait BatchRequest: Sync {
+    /// A single element of the request, e.g. a pair address.
+    type Input: Clone + Send + Sync;
+    /// The decoded result for a single input.
+    type Output: Send;
+
+    /// Name of the request, used in progress output.
+    fn name(&self) -> &'static str;
+
+    /// ABI of the batch contract, its constructor is used to encode the arguments.
+    fn abi(&self) -> &Abi;
+
+    /// Creation bytecode of the batch contract.
+    fn bytecode(&self) -> &Bytes;
+
+    /// Constructor arguments for a chunk of inputs.
+    fn constructor_args(&self, inputs: &[Self::Input]) -> Vec<Token>;
+
+    /// ABI type of a single element of the array returned by the contract.
+    fn return_type(&self) -> ParamType;
+
+    /// Decodes the element returned for `input`. Inputs whose element cannot be decoded are
+    /// reported as failed instead of failing the whole chunk.
+    fn decode(&self, input: &Self::Input, token: Token) -> Result<Self::Output, BatchDecodeError>;
+
+    /// Fetches the same outputs through Multicall3 `aggregate3`, honouring the block and state
+    /// overrides of `options`. The `aggregate3` calls are measured into `telemetry` when set.
+    /// Returns `None` when the request has no Multicall3 equivalent.
+    async fn multicall<M: Middleware>(
+        &self,
+        _inputs: &[Self::Input],
+        _middleware: Arc<M>,
+        _options: &BatchRequestOptions,
+        _telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Option<Result<Vec<Self::Output>, AMMError<M>>> {
+        None
+    }
+}
+
+/// How batch requests reach the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchBackend {
+    /// `eth_call` with the creation bytecode of the batch contract.
+    Deployless,
+    /// Multicall3 `aggregate3`, failing for requests without a Multicall3 equivalent.
+    Multicall3,
+    /// Deployless, falling back to Multicall3 when the deployless call fails. Once the fallback
+    /// succeeded, later calls on the same chain go to Multicall3 directly.
+    #[default]
+    Auto,
+}
+
+lazy_static! {
+    /// Chains on which a deployless call failed where Multicall3 succeeded, i.e. whose node
+    /// rejects them.
+    static ref DEPLOYLESS_REJECTED: Mutex<HashSet<U256>> = Mutex::new(HashSet::new());
+    /// Chunk sizes learned for each request, by [`BatchRequest::name`].
+    static ref BATCH_SIZES: Mutex<HashMap<&'static str, LearnedSize>> = Mutex::new(HashMap::new());
+    static ref BATCH_LIMITS: RwLock<BatchLimits> = RwLock::new(BatchLimits::default());
+}
+
+/// Half of the default `eth_call` gas cap of geth, 50M.
+pub const DEFAULT_BATCH_GAS_LIMIT: u64 = 25_000_000;
+pub const DEFAULT_BATCH_RESPONSE_SIZE_LIMIT: usize = 4 * 1024 * 1024;
+/// Chunk size used when no step is given and none was learned or tuned.
+pub const DEFAULT_BATCH_STEP: usize = 100;
+
+/// Per call limits of the node, chunks are sized to stay under them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchLimits {
+    /// Gas a single call may use.
+    pub gas: Option<u64>,
+    /// Size in bytes of a single response.
+    pub response_size: Option<usize>,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        BatchLimits {
+            gas: Some(DEFAULT_BATCH_GAS_LIMIT),
+            response_size: Some(DEFAULT_BATCH_RESPONSE_SIZE_LIMIT),
+        }
+    }
+}
+
+impl BatchLimits {
+    /// No limits, chunks keep the configured step.
+    pub fn none() -> Self {
+        BatchLimits {
+            gas: None,
+            response_size: None,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.gas.is_some() || self.response_size.is_some()
+    }
+
+    /// Largest chunk size staying under the limits given the measured calls, `None` without
+    /// measurements to go by.
+    fn tune(&self, telemetry: &[CallTelemetry]) -> Option<usize> {
+        let per_input = |measure: fn(&CallTelemetry) -> Option<u64>| {
+            telemetry
+                .iter()
+                .filter(|call| call.inputs > 0)
+                .filter_map(|call| Some(measure(call)?.div_ceil(call.inputs as u64)))
+                .max()
+                .filter(|per_input| *per_input > 0)
+        };
+        let by_gas = self
+            .gas
+            .zip(per_input(|call| call.gas_used))
+            .map(|(limit, per_input)| limit / per_input);
+        let by_size = self
+            .response_size
+            .zip(per_input(|call| Some(call.response_size as u64)))
+            .map(|(limit, per_input)| limit as u64 / per_input);
+
+        let size = match (by_gas, by_size) {
+            (Some(by_gas), Some(by_size)) => by_gas.min(by_size),
+            (by_gas, by_size) => by_gas.or(by_size)?,
+        };
+        Some((size as usize).max(1))
+    }
+}
+
+/// The limits used by default for every batch request.
+pub fn batch_limits() -> BatchLimits {
+    *BATCH_LIMITS.read().unwrap()
+}
+
+/// Sets the limits of the connected node, used by default for every batch request. Forgets the
+/// chunk sizes tuned under the previous limits.
+pub fn set_batch_limits(limits: BatchLimits) {
+    *BATCH_LIMITS.write().unwrap() = limits;
+    reset_batch_sizes();
+}
+
+/// Measurements of a single deployless or Multicall3 call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallTelemetry {
+    pub inputs: usize,
+    /// Gas estimated for the call, `None` when the estimation failed.
+    pub gas_used: Option<u64>,
+    /// Size in bytes of the response.
+    pub response_size: usize,
+}
+
+/// Chunk sizes learned for a request, the smaller of the two is used.
+#[derive(Debug, Clone, Copy, Default)]
+struct LearnedSize {
+    /// Tuned to the limits by measuring a first chunk.
+    tuned: Option<usize>,
+    /// Largest chunk that worked after a chunk had to be bisected.
+    bisected: Option<usize>,
+}
+
+impl LearnedSize {
+    fn size(&self) -> Option<usize> {
+        match (self.tuned, self.bisected) {
+            (Some(tuned), Some(bisected)) => Some(tuned.min(bisected)),
+            (tuned, bisected) => tuned.or(bisected),
+        }
+    }
+}
+
+/// The chunk size remembered for the request named `name`, if it was tuned or a chunk of it
+/// needed to be bisected, the smaller of the two when both happened.
+pub fn learned_batch_size(name: &str) -> Option<usize> {
+    BATCH_SIZES
+        .lock()
+        .unwrap()
+        .get(name)
+        .and_then(LearnedSize::size)
+}
+
+/// Forgets the chunk sizes learned for every request, e.g. after switching nodes.
+pub fn reset_batch_sizes() {
+    BATCH_SIZES.lock().unwrap().clear();
+}
+
+/// Forgets the chains whose node rejected deployless calls, e.g. after switching nodes.
+pub fn reset_deployless_rejected() {
+    DEPLOYLESS_REJECTED.lock().unwrap().clear();
+}
+
+/// Whether the node of the chain `middleware` is connected to rejected deployless calls before.
+/// The chain id is only fetched once some chain was recorded.
+async fn deployless_rejected<M: Middleware>(middleware: &M) -> bool {
+    if DEPLOYLESS_REJECTED.lock().unwrap().is_empty() {
+        return false;
+    }
+    match middleware.get_chainid().await {
+        Ok(chain_id) => DEPLOYLESS_REJECTED.lock().unwrap().contains(&chain_id),
+        Err(_) => false,
+    }
+}
+
+async fn record_deployless_rejected<M: Middleware>(middleware: &M) {
+    if let Ok(chain_id) = middleware.get_chainid().await {
+        DEPLOYLESS_REJECTED.lock().unwrap().insert(chain_id);
+    }
+}
+
+fn set_tuned_batch_size(name: &'static str, size: usize) {
+    BATCH_SIZES.lock().unwrap().entry(name).or_default().tuned = Some(size);
+}
+
+/// Remembers that chunks of `size` inputs worked for the request named `name` after a larger
+/// chunk had to be bisected, keeping the smallest size recorded.
+pub(crate) fn record_batch_size(name: &'static str, size: usize) {
+    let mut batch_sizes = BATCH_SIZES.lock().unwrap();
+    let bisected = &mut batch_sizes.entry(name).or_default().bisected;
+    *bisected = Some(bisected.map_or(size, |bisected| bisected.min(size)));
+}
+
+/// Grows the bisected size of the request named `name` by one input after a chunk of `size`
+/// inputs worked, until it reaches the tuned size or [`DEFAULT_BATCH_STEP`] and is forgotten.
+fn record_batch_success(name: &'static str, size: usize) {
+    let mut batch_sizes = BATCH_SIZES.lock().unwrap();
+    let Some(learned) = batch_sizes.get_mut(name) else {
+        return;
+    };
+    if let Some(bisected) = learned.bisected.filter(|bisected| size >= *bisected) {
+        let ceiling = learned.tuned.unwrap_or(DEFAULT_BATCH_STEP);
+        learned.bisected = Some(bisected + 1).filter(|grown| *grown < ceiling);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchRequestOptions {
+    /// Number of inputs sent in a single call. When unset, the size learned for the request is
+    /// used, or tuned to `limits` by measuring a first chunk of [`DEFAULT_BATCH_STEP`] inputs.
+    pub step: Option<usize>,
+    /// Maximum number of calls in flight.
+    pub concurrency: usize,
+    /// Number of times a failed call is retried before its chunk is bisected.
+    pub retries: usize,
+    /// Whether to print the request and show a progress bar.
+    pub progress: bool,
+    /// Block every call runs against, `latest` when unset.
+    pub block: Option<BlockId>,
+    /// State overrides applied to every call, e.g. to query hypothetical reserves or balances.
+    /// Not all nodes support them.
+    pub state: Option<spoof::State>,
+    pub backend: BatchBackend,
+    /// Limits the chunk size is tuned to, [`batch_limits`] by default.
+    pub limits: BatchLimits,
+    /// Whether to measure every call, reported in [`BatchResponse::telemetry`]. Gas is measured
+    /// with an extra `eth_estimateGas` per call.
+    pub telemetry: bool,
+}
+
+impl Default for BatchRequestOptions {
+    fn default() -> Self {
+        BatchRequestOptions {
+            step: None,
+            concurrency: 32,
+            retries: 2,
+            progress: false,
+            block: None,
+            state: None,
+            backend: BatchBackend::default(),
+            limits: batch_limits(),
+            telemetry: false,
+        }
+    }
+}
+
+/// Inputs paired with their decoded outputs.
+pub type BatchOutputs<R> = Vec<(<R as BatchRequest>::Input, <R as BatchRequest>::Output)>;
+
+/// Outputs of a single call, one per input of the chunk.
+type ChunkOutputs<R> = Vec<Result<<R as BatchRequest>::Output, BatchDecodeError>>;
+
+pub struct BatchResponse<R: BatchRequest, M: Middleware> {
+    /// Decoded outputs, in the order of the inputs.
+    pub outputs: BatchOutputs<R>,
+    /// Inputs that still failed on their own after bisecting their chunk, with the last error, and
+    /// inputs whose output could not be decoded.
+    pub failed: Vec<(R::Input, AMMError<M>)>,
+    /// Measurements of the calls that were measured, see [`BatchRequestOptions::telemetry`].
+    pub telemetry: Vec<CallTelemetry>,
+}
+
+impl<R: BatchRequest, M: Middleware> BatchResponse<R, M> {
+    /// Returns the outputs, or the error of the first failed input.
+    pub fn into_outputs(self) -> Result<BatchOutputs<R>, AMMError<M>> {
+        match self.failed.into_iter().next() {
+            Some((_, err)) => Err(err),
+            None => Ok(self.outputs),
+        }
+    }
+}
+
+pub async fn execute_batch_request<R: BatchRequest, M: Middleware>(
+    request: &R,
+    inputs: &[R::Input],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+) -> BatchResponse<R, M> {
+    let progress_bar = if options.progress {
+        println!(
+            "Running {} batch request for {} inputs",
+            request.name(),
+            inputs.len()
+        );
+        Some(ProgressBar::new(inputs.len() as u64))
+    } else {
+        None
+    };
+
+    let mut response = BatchResponse {
+        outputs: Vec::with_capacity(inputs.len()),
+        failed: vec![],
+        telemetry: vec![],
+    };
+    let telemetry = Mutex::new(vec![]);
+    let measured = options.telemetry.then_some(&telemetry);
+    let deployless_rejected = AtomicBool::new(
+        options.backend == BatchBackend::Auto && deployless_rejected(middleware.as_ref()).await,
+    );
+
+    let mut remaining = inputs;
+    let step = match (options.step, learned_batch_size(request.name())) {
+        (Some(step), _) => step,
+        (None, Some(learned)) => learned,
+        (None, None) if options.limits.is_enabled() && !inputs.is_empty() => {
+            // Measure a first chunk to size the remaining ones
+            let (probe, rest) = inputs.split_at(DEFAULT_BATCH_STEP.min(inputs.len()));
+            let (outputs, failed) = call_with_bisection(
+                request,
+                probe,
+                middleware.clone(),
+                options,
+                Some(&telemetry),
+                &deployless_rejected,
+            )
+            .await;
+            response.outputs.extend(outputs);
+            response.failed.extend(failed);
+            if let Some(progress_bar) = &progress_bar {
+                progress_bar.inc(probe.len() as u64);
+            }
+            remaining = rest;
+
+            match options.limits.tune(&telemetry.lock().unwrap()) {
+                Some(tuned) => {
+                    set_tuned_batch_size(request.name(), tuned);
+                    tuned
+                }
+                None => DEFAULT_BATCH_STEP,
+            }
+        }
+        (None, None) => DEFAULT_BATCH_STEP,
+    }
+    .max(1);
+
+    let results: Vec<_> = stream::iter(remaining.chunks(step))
+        .map(|chunk| {
+            let middleware = middleware.clone();
+            let progress_bar = progress_bar.clone();
+            let deployless_rejected = &deployless_rejected;
+            async move {
+                let result = call_with_bisection(
+                    request,
+                    chunk,
+                    middleware,
+                    options,
+                    measured,
+                    deployless_rejected,
+                )
+                .await;
+                if let Some(progress_bar) = progress_bar {
+                    progress_bar.inc(chunk.len() as u64);
+                }
+                result
+            }
+        })
+        .buffered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    for (outputs, failed) in results {
+        response.outputs.extend(outputs);
+        response.failed.extend(failed);
+    }
+    response.telemetry = telemetry.into_inner().unwrap();
+
+    if let Some(progress_bar) = progress_bar {
+        progress_bar.finish();
+    }
+    if options.progress && !response.telemetry.is_empty() {
+        report_telemetry(request.name(), &response.telemetry);
+    }
+    response
+}
+
+fn report_telemetry(name: &str, telemetry: &[CallTelemetry]) {
+    let inputs: usize = telemetry.iter().map(|call| call.inputs).sum();
+    let gas_used: u64 = telemetry.iter().filter_map(|call| call.gas_used).sum();
+    let response_size: usize = telemetry.iter().map(|call| call.response_size).sum();
+    println!(
+        "{} batch request: {} calls for {} inputs, {} gas, {} bytes",
+        name,
+        telemetry.len(),
+        inputs,
+        gas_used,
+        response_size
+    );
+}
+
+/// Calls `inputs` as a single chunk, halving the chunks that fail until the failing inputs are
+/// isolated. Stops at the first call that fails to reach the node.
+async fn call_with_bisection<R: BatchRequest, M: Middleware>(
+    request: &R,
+    inputs: &[R::Input],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    deployless_rejected: &AtomicBool,
+) -> (BatchOutputs<R>, Vec<(R::Input, AMMError<M>)>) {
+    let mut outputs = Vec::with_capacity(inputs.len());
+    let mut failed = vec![];
+    let mut largest_bisected_chunk = None;
+
+    // Depth first, left half first, so that the outputs stay in the order of the inputs
+    let mut pending = vec![inputs];
+    while let Some(chunk) = pending.pop() {
+        match call_with_retries(
+            request,
+            chunk,
+            middleware.clone(),
+            options,
+            telemetry,
+            deployless_rejected,
+        )
+        .await
+        {
+            Ok(chunk_outputs) => {
+                if chunk.len() < inputs.len() {
+                    largest_bisected_chunk = largest_bisected_chunk.max(Some(chunk.len()));
+                }
+                for (input, output) in chunk.iter().cloned().zip(chunk_outputs) {
+                    match output {
+                        Ok(output) => outputs.push((input, output)),
+                        Err(err) => failed.push((input, err.into())),
+                    }
+                }
+            }
+            // Smaller chunks do not help when the node cannot be reached, the inputs left are
+            // failed without calling them
+            Err(err) if is_transport_error(&err) => {
+                let mut uncalled = pending.into_iter().rev().flatten();
+                let first = chunk[0].clone();
+                failed.extend(
+                    chunk[1..].iter().chain(&mut uncalled).map(|input| {
+                        (input.clone(), AMMError::BatchRequestAborted(request.name()))
+                    }),
+                );
+                failed.push((first, err));
+                break;
+            }
+            Err(err) if chunk.len() == 1 => failed.push((chunk[0].clone(), err)),
+            Err(_) => {
+                let (left, right) = chunk.split_at(chunk.len() / 2);
+                pending.push(right);
+                pending.push(left);
+            }
+        }
+    }
+
+    // A chunk that only worked once smaller was too large, rather than holding a broken input
+    let any_call_failed = failed
+        .iter()
+        .any(|(_, err)| !matches!(err, AMMError::BatchDecodeError(_)));
+    match (largest_bisected_chunk, any_call_failed) {
+        (Some(size), false) => record_batch_size(request.name(), size),
+        (None, false) if !inputs.is_empty() => record_batch_success(request.name(), inputs.len()),
+        _ => {}
+    }
+
+    (outputs, failed)
+}
+
+async fn call_with_retries<R: BatchRequest, M: Middleware>(
+    request: &R,
+    inputs: &[R::Input],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    deployless_rejected: &AtomicBool,
+) -> Result<ChunkOutputs<R>, AMMError<M>> {
+    let mut attempt = 0;
+    loop {
+        match call_batch_backend(
+            request,
+            inputs,
+            middleware.clone(),
+            options,
+            telemetry,
+            deployless_rejected,
+        )
+        .await
+        {
+            Ok(outputs) => return Ok(outputs),
+            Err(err) if attempt >= options.retries => return Err(err),
+            Err(_) => {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+            }
+        }
+    }
+}
+
+async fn call_batch_backend<R: BatchRequest, M: Middleware>(
+    request: &R,
+    inputs: &[R::Input],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    deployless_rejected: &AtomicBool,
+) -> Result<ChunkOutputs<R>, AMMError<M>> {
+    match options.backend {
+        BatchBackend::Deployless => {
+            call_batch_request(request, inputs, middleware, options, telemetry).await
+        }
+        BatchBackend::Multicall3 => call_multicall(request, inputs, middleware, options, telemetry)
+            .await
+            .unwrap_or(Err(AMMError::MulticallUnsupported(request.name()))),
+        BatchBackend::Auto if deployless_rejected.load(Ordering::Relaxed) => {
+            match call_multicall(request, inputs, middleware.clone(), options, telemetry).await {
+                Some(result) => result,
+                None => call_batch_request(request, inputs, middleware, options, telemetry).await,
+            }
+        }
+        BatchBackend::Auto => {
+            match call_batch_request(request, inputs, middleware.clone(), options, telemetry).await
+            {
+                Ok(outputs) => Ok(outputs),
+                Err(err) => {
+                    match call_multicall(request, inputs, middleware.clone(), options, telemetry)
+                        .await
+                    {
+                        Some(Ok(outputs)) => {
+                            if !deployless_rejected.swap(true, Ordering::Relaxed) {
+                                record_deployless_rejected(middleware.as_ref()).await;
+                            }
+                            Ok(outputs)
+                        }
+                        _ => Err(err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn call_multicall<R: BatchRequest, M: Middleware>(
+    request: &R,
+    inputs: &[R::Input],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+) -> Option<Result<ChunkOutputs<R>, AMMError<M>>> {
+    let calls = telemetry.map(|_| Mutex::new(vec![]));
+    let result = request
+        .multicall(inputs, middleware, options, calls.as_ref())
+        .await?;
+
+    // A request may need several `aggregate3` calls, they are reported as a single call
+    if let (Some(telemetry), Some(calls)) = (telemetry, calls) {
+        let calls = calls.into_inner().unwrap();
+        if result.is_ok() && !calls.is_empty() {
+            telemetry.lock().unwrap().push(CallTelemetry {
+                inputs: inputs.len(),
+                gas_used: calls.iter().map(|call| call.gas_used).sum(),
+                response_size: calls.iter().map(|call| call.response_size).sum(),
+            });
+        }
+    }
+    Some(result.map(|outputs| outputs.into_iter().map(Ok).collect()))
+}
+
+async fn call_batch_request<R: BatchRequest, M: Middleware>(
+    request: &R,
+    inputs: &[R::Input],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+) -> Result<ChunkOutputs<R>, AMMError<M>> {
+    let deployer = ContractFactory::new(
+        request.abi().clone(),
+        request.bytecode().clone(),
+        middleware.clone(),
+    )
+    .deploy_tokens(request.constructor_args(inputs))?;
+    let mut call = deployer.call_raw();
+    if let Some(block) = options.block {
+        call = call.block(block);
+    }
+    if let Some(state) = &options.state {
+        call = call.state(state);
+    }
+    let return_data: Bytes = call.await?;
+
+    if let Some(telemetry) = telemetry {
+        // State overrides are not supported by eth_estimateGas, the estimate runs without them
+        let gas_used = middleware
+            .estimate_gas(&deployer.tx, options.block)
+            .await
+            .ok()
+            .map(|gas| gas.as_u64());
+        telemetry.lock().unwrap().push(CallTelemetry {
+            inputs: inputs.len(),
+            gas_used,
+            response_size: return_data.len(),
+        });
+    }
+    let return_data_tokens = ethers::abi::decode(
+        &[ParamType::Array(Box::new(request.return_type()))],
+        &return_data,
+    )?;
+
+    let tokens = return_data_tokens
+        .into_iter()
+        .next()
+        .and_then(|token| token.into_array())
+        .filter(|tokens| tokens.len() == inputs.len())
+        .ok_or(AMMError::InvalidBatchRequestOutput(request.name()))?;
+
+    Ok(inputs
+        .iter()
+        .zip(tokens)
+        .map(|(input, token)| request.decode(input, token))
+        .collect())
+}
+
+/// Whether `err` comes from failing to reach the node, as opposed to an error response of the
+/// node to the call itself such as a revert or running out of gas.
+pub(crate) fn is_transport_error<M: Middleware>(err: &AMMError<M>) -> bool {
+    match err {
+        AMMError::ProviderError(err) => !RpcError::is_error_response(err),
+        AMMError::MiddlewareError(err) => !err.is_error_response(),
+        AMMError::ContractError(err) => {
+            match (err.as_middleware_error(), err.as_provider_error()) {
+                (Some(err), _) => !err.is_error_response(),
+                (_, Some(err)) => !RpcError::is_error_response(err),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Resolves `block` to a block number, so that a run can be pinned to a single block. Defaults to
+/// the current block.
+pub async fn resolve_block_number<M: Middleware>(
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+) -> Result<u64, AMMError<M>> {
+    match block {
+        Some(BlockId::Number(BlockNumber::Number(number))) => Ok(number.as_u64()),
+        Some(block) => Ok(middleware
+            .get_block(block)
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .and_then(|block| block.number)
+            .ok_or(AMMError::BlockNumberNotFound)?
+            .as_u64()),
+        None => Ok(middleware
+            .get_block_number()
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .as_u64()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tune_batch_size() {
+        let limits = BatchLimits {
+            gas: Some(10_000_000),
+            response_size: Some(64 * 1024),
+        };
+        let telemetry = [
+            CallTelemetry {
+                inputs: 100,
+                gas_used: Some(2_000_000),
+                response_size: 22_400,
+            },
+            CallTelemetry {
+                inputs: 50,
+                gas_used: Some(1_500_000),
+                response_size: 11_200,
+            },
+        ];
+
+        // 30k gas per input caps the chunk at 333 inputs, 224 bytes per input at 292
+        assert_eq!(limits.tune(&telemetry), Some(292));
+        assert_eq!(
+            BatchLimits {
+                response_size: None,
+                ..limits
+            }
+            .tune(&telemetry),
+            Some(333)
+        );
+        assert_eq!(limits.tune(&[]), None);
+        assert_eq!(BatchLimits::none().tune(&telemetry), None);
+    }
+
+    #[test]
+    fn test_learned_batch_size() {
+        let name = "test_learned_batch_size";
+        record_batch_size(name, 50);
+        set_tuned_batch_size(name, 80);
+        assert_eq!(learned_batch_size(name), Some(50));
+
+        // Successes grow the bisected size back until it reaches the tuned one
+        record_batch_success(name, 10);
+        assert_eq!(learned_batch_size(name), Some(50));
+        for size in 50..79 {
+            record_batch_success(name, size);
+        }
+        assert_eq!(learned_batch_size(name), Some(79));
+        record_batch_success(name, 79);
+        assert_eq!(learned_batch_size(name), Some(80));
+
+        // Tuning does not override a smaller bisected size
+        record_batch_size(name, 30);
+        set_tuned_batch_size(name, 60);
+        assert_eq!(learned_batch_size(name), Some(30));
+        set_tuned_batch_size(name, 20);
+        assert_eq!(learned_batch_size(name), Some(20));
+    }
+
+    #[tokio::test]
+    async fn test_bisection_stops_on_transport_error() {
+        use crate::uniswap_v2::batch_request::UniswapV2ReservesBatchRequest;
+        use ethers::{providers::Provider, types::H160};
+
+        // Without queued responses, every request to the mock fails like a dropped connection
+        let (provider, _mock) = Provider::mocked();
+        let inputs: Vec<H160> = (0..100).map(H160::from_low_u64_be).collect();
+        let options = BatchRequestOptions {
+            backend: BatchBackend::Deployless,
+            retries: 0,
+            ..Default::default()
+        };
+        let (outputs, failed) = call_with_bisection(
+            &UniswapV2ReservesBatchRequest,
+            &inputs,
+            Arc::new(provider),
+            &options,
+            None,
+            &AtomicBool::new(false),
+        )
+        .await;
+
+        assert!(outputs.is_empty());
+        assert_eq!(failed.len(), inputs.len());
+        let aborted = failed
+            .iter()
+            .filter(|(_, err)| matches!(err, AMMError::BatchRequestAborted(_)))
+            .count();
+        assert_eq!(aborted, inputs.len() - 1);
+    }
+
+    #[test]
+    fn test_is_transport_error() {
+        use ethers::providers::{Http, HttpClientError, JsonRpcError, Provider, ProviderError};
+
+        let reverted = ProviderError::from(HttpClientError::JsonRpcError(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        assert!(!is_transport_error::<Provider<Http>>(&reverted.into()));
+        let dropped = ProviderError::CustomError("connection closed".to_string());
+        assert!(is_transport_error::<Provider<Http>>(&dropped.into()));
+    }
+}
@@ -8,6 +8,7 @@ use std::sync::Arc;
 use std::{collections::HashMap, fs};
 
 use crate::errors::AMMError;
+use crate::tokens::TokenCache;
 use crate::uniswap_v2::factory::UniswapV2Factory;
 use crate::uniswap_v2::UniswapV2Pool;
 
@@ -16,6 +17,7 @@ pub enum ConfigError {
     EnvVarMissing(String),
     MiddlewareInitError(String),
     TokensLoadError(String),
+    TokenCacheLoadError(String),
     UniswapPairsLoadError(String),
 }
 
@@ -27,6 +29,7 @@ impl fmt::Display for ConfigError {
                 write!(f, "Middleware initialization error: {}", e)
             }
             ConfigError::TokensLoadError(e) => write!(f, "Tokens loading error: {}", e),
+            ConfigError::TokenCacheLoadError(e) => write!(f, "Token cache loading error: {}", e),
             ConfigError::UniswapPairsLoadError(e) => {
                 write!(f, "Uniswap pairs loading error: {}", e)
             }
@@ -36,9 +39,15 @@ impl fmt::Display for ConfigError {
 
 impl Error for ConfigError {}
 
+/// Where the token metadata fetched by the playground is cached.
+pub const TOKEN_CACHE_PATH: &str = "checkpoint_data/erc20_tokens.json";
+
 pub struct Config {
     pub middleware: Arc<Provider<Http>>,
     pub tokens: HashMap<String, H160>,
+    pub token_cache_path: String,
+    /// The cache at `token_cache_path`, empty when there is none yet.
+    pub token_cache: TokenCache,
     pub uniswap_v2_pairs: HashMap<String, HashMap<String, H160>>,
     pub uniswap_v2_factory: UniswapV2Factory,
 }
@@ -53,9 +62,14 @@ impl Config {
                 .map_err(|e| ConfigError::MiddlewareInitError(e.to_string()))?,
         );
 
+        let token_cache = TokenCache::read_from_path_or_default(TOKEN_CACHE_PATH)
+            .map_err(|e| ConfigError::TokenCacheLoadError(e.to_string()))?;
+
         Ok(Config {
             middleware,
             tokens: Self::load_tokens(),
+            token_cache_path: TOKEN_CACHE_PATH.to_string(),
+            token_cache,
             uniswap_v2_pairs: Self::load_uniswap_v2_pairs(),
             uniswap_v2_factory: UniswapV2Factory::new(
                 H160::from_str("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f").unwrap(),
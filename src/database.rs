@@ -0,0 +1,677 @@
+use std::{path::Path, str::FromStr, sync::Mutex};
+
+use ethers::types::{H160, H256, U256};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::{
+    errors::{CheckpointError, DatabaseError},
+    tokens::Token,
+    uniswap_v2::{
+        batch_request::RejectReason,
+        checkpoint::{Checkpoint, CheckpointKey, CheckpointStore, CHECKPOINT_VERSION},
+        factory::{PoolCreation, UniswapV2Factory},
+        UniswapV2Pool,
+    },
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS factories (
+    address TEXT PRIMARY KEY,
+    creation_block INTEGER NOT NULL,
+    fee INTEGER NOT NULL,
+    synced_block INTEGER NOT NULL,
+    synced_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS tokens (
+    address TEXT PRIMARY KEY,
+    symbol TEXT,
+    name TEXT,
+    decimals INTEGER,
+    total_supply TEXT
+);
+CREATE TABLE IF NOT EXISTS pools (
+    address TEXT PRIMARY KEY,
+    factory TEXT NOT NULL,
+    token_a TEXT NOT NULL,
+    token_a_decimals INTEGER NOT NULL,
+    token_b TEXT NOT NULL,
+    token_b_decimals INTEGER NOT NULL,
+    fee INTEGER NOT NULL,
+    creation_block INTEGER,
+    creation_transaction TEXT,
+    creation_log_index INTEGER,
+    pair_index INTEGER
+);
+CREATE INDEX IF NOT EXISTS pools_factory ON pools (factory);
+CREATE INDEX IF NOT EXISTS pools_token_a ON pools (token_a);
+CREATE INDEX IF NOT EXISTS pools_token_b ON pools (token_b);
+CREATE TABLE IF NOT EXISTS rejected_pools (
+    address TEXT PRIMARY KEY,
+    factory TEXT NOT NULL,
+    reason TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS reserves (
+    pool TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    block_timestamp_last INTEGER NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    reserve_0 TEXT NOT NULL,
+    reserve_1 TEXT NOT NULL,
+    PRIMARY KEY (pool, block_number)
+);
+CREATE INDEX IF NOT EXISTS reserves_recorded_at ON reserves (pool, recorded_at);
+";
+
+const POOL_COLUMNS: &str = "p.address, p.factory, p.token_a, p.token_a_decimals, p.token_b,
+    p.token_b_decimals, p.fee, p.creation_block, p.creation_transaction, p.creation_log_index,
+    p.pair_index, r.reserve_0, r.reserve_1, r.block_timestamp_last, r.block_number";
+
+/// SQLite store of the factories, tokens and pools of one chain, along with the reserves of
+/// every pool each time they were saved.
+///
+/// It implements [`CheckpointStore`], so the sync functions write to it as they sync. Each save
+/// only writes the pools that are new or changed and appends the new reserves, reserves at a
+/// block already stored are kept. The tokens of
+/// saved pools are recorded with their decimals, [`PoolDatabase::save_tokens`] adds the rest of
+/// their metadata.
+pub struct PoolDatabase {
+    connection: Mutex<Connection>,
+}
+
+impl PoolDatabase {
+    pub fn open(path: impl AsRef<Path>) -> Result<PoolDatabase, DatabaseError> {
+        PoolDatabase::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<PoolDatabase, DatabaseError> {
+        PoolDatabase::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<PoolDatabase, DatabaseError> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(PoolDatabase {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Chain of the stored factories, set by the first saved checkpoint.
+    pub fn chain_id(&self) -> Result<Option<u64>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let chain_id: Option<String> = connection
+            .query_row("SELECT value FROM meta WHERE key = 'chain_id'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        chain_id.map(|chain_id| parse(&chain_id)).transpose()
+    }
+
+    /// Inserts or replaces the metadata of `tokens`, e.g. as fetched by
+    /// [`TokenCache::fetch_missing_for_pools`](crate::tokens::TokenCache::fetch_missing_for_pools).
+    pub fn save_tokens(&self, tokens: &[Token]) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO tokens (address, symbol, name, decimals, total_supply)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for token in tokens {
+                statement.execute(params![
+                    format!("{:?}", token.address),
+                    token.symbol,
+                    token.name,
+                    token.decimals,
+                    token
+                        .total_supply
+                        .map(|total_supply| total_supply.to_string()),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn token(&self, address: H160) -> Result<Option<Token>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let row = connection
+            .query_row(
+                "SELECT symbol, name, decimals, total_supply FROM tokens WHERE address = ?1",
+                [format!("{:?}", address)],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<String>>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<u8>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        row.map(|(symbol, name, decimals, total_supply)| {
+            Ok(Token {
+                address,
+                symbol,
+                name,
+                decimals,
+                total_supply: total_supply
+                    .map(|total_supply| {
+                        U256::from_dec_str(&total_supply)
+                            .map_err(|_| DatabaseError::InvalidValue(total_supply))
+                    })
+                    .transpose()?,
+            })
+        })
+        .transpose()
+    }
+
+    /// Inserts or updates `pools` and appends their reserves.
+    pub fn save_pools(&self, pools: &[UniswapV2Pool]) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        insert_pools(&transaction, pools, None)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Pool with its latest stored reserves.
+    pub fn pool(&self, address: H160) -> Result<Option<UniswapV2Pool>, DatabaseError> {
+        Ok(self
+            .query_pools(
+                "WHERE p.address = ?1 ORDER BY r.block_number DESC LIMIT 1",
+                params![format!("{:?}", address)],
+            )?
+            .pop())
+    }
+
+    /// Pool with the last reserves stored at or before `block_number`.
+    pub fn pool_at_block(
+        &self,
+        address: H160,
+        block_number: u64,
+    ) -> Result<Option<UniswapV2Pool>, DatabaseError> {
+        Ok(self
+            .query_pools(
+                "WHERE p.address = ?1 AND r.block_number <= ?2
+                ORDER BY r.block_number DESC LIMIT 1",
+                params![format!("{:?}", address), block_number],
+            )?
+            .pop())
+    }
+
+    /// Pool with the last reserves saved at or before the unix `timestamp`. This is the time the
+    /// reserves were saved, not the timestamp of the block they were fetched at, see
+    /// [`PoolDatabase::pool_at_block`] for the reserves at a block.
+    pub fn pool_saved_at(
+        &self,
+        address: H160,
+        timestamp: u64,
+    ) -> Result<Option<UniswapV2Pool>, DatabaseError> {
+        Ok(self
+            .query_pools(
+                "WHERE p.address = ?1 AND r.recorded_at <= ?2
+                ORDER BY r.recorded_at DESC, r.block_number DESC LIMIT 1",
+                params![format!("{:?}", address), timestamp],
+            )?
+            .pop())
+    }
+
+    /// Every stored reserves of the pool, oldest first, as pools.
+    pub fn reserve_history(&self, address: H160) -> Result<Vec<UniswapV2Pool>, DatabaseError> {
+        self.query_pools(
+            "WHERE p.address = ?1 ORDER BY r.block_number",
+            params![format!("{:?}", address)],
+        )
+    }
+
+    /// Pools with `token` on either side, with their latest reserves.
+    pub fn pools_with_token(&self, token: H160) -> Result<Vec<UniswapV2Pool>, DatabaseError> {
+        self.query_pools(
+            "WHERE (p.token_a = ?1 OR p.token_b = ?1) AND r.block_number =
+                (SELECT MAX(block_number) FROM reserves WHERE pool = p.address)
+            ORDER BY p.address",
+            params![format!("{:?}", token)],
+        )
+    }
+
+    /// Pools of `factory` with their latest reserves.
+    pub fn factory_pools(&self, factory: H160) -> Result<Vec<UniswapV2Pool>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        factory_pools(&connection, factory)
+    }
+
+    fn query_pools(
+        &self,
+        clause: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<UniswapV2Pool>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        query_pools(&connection, clause, params)
+    }
+}
+
+impl CheckpointStore for PoolDatabase {
+    fn load(&self, key: &CheckpointKey) -> Result<Option<Checkpoint>, CheckpointError> {
+        Ok(self.load_checkpoint(key)?)
+    }
+
+    fn save(&self, key: &CheckpointKey, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        Ok(self.save_checkpoint(key, checkpoint)?)
+    }
+}
+
+impl PoolDatabase {
+    fn load_checkpoint(&self, key: &CheckpointKey) -> Result<Option<Checkpoint>, DatabaseError> {
+        if let Some(chain_id) = self.chain_id()? {
+            check_chain_id(chain_id, key)?;
+        }
+        let connection = self.connection.lock().unwrap();
+        let factory = connection
+            .query_row(
+                "SELECT creation_block, fee, synced_block, synced_at FROM factories
+                WHERE address = ?1",
+                [format!("{:?}", key.factory)],
+                |row| {
+                    Ok((
+                        row.get::<_, u64>(0)?,
+                        row.get::<_, u32>(1)?,
+                        row.get::<_, u64>(2)?,
+                        row.get::<_, usize>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((creation_block, fee, synced_block, synced_at)) = factory else {
+            return Ok(None);
+        };
+
+        let pools = factory_pools(&connection, key.factory)?;
+        let mut statement =
+            connection.prepare("SELECT address, reason FROM rejected_pools WHERE factory = ?1")?;
+        let rejected = statement
+            .query_map([format!("{:?}", key.factory)], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|row| {
+                let (address, reason) = row?;
+                let reason: RejectReason = serde_json::from_str(&reason)?;
+                Ok((parse(&address)?, reason))
+            })
+            .collect::<Result<Vec<_>, DatabaseError>>()?;
+
+        Ok(Some(Checkpoint {
+            version: CHECKPOINT_VERSION,
+            timestamp: synced_at,
+            block_number: synced_block,
+            factory: UniswapV2Factory::new(key.factory, creation_block, fee),
+            pools,
+            rejected,
+        }))
+    }
+
+    fn save_checkpoint(
+        &self,
+        key: &CheckpointKey,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO meta (key, value) VALUES ('chain_id', ?1)",
+            [key.chain_id.to_string()],
+        )?;
+        let chain_id: String =
+            transaction.query_row("SELECT value FROM meta WHERE key = 'chain_id'", [], |row| {
+                row.get(0)
+            })?;
+        check_chain_id(parse(&chain_id)?, key)?;
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO factories
+            (address, creation_block, fee, synced_block, synced_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                format!("{:?}", key.factory),
+                checkpoint.factory.creation_block,
+                checkpoint.factory.fee,
+                checkpoint.block_number,
+                checkpoint.timestamp,
+            ],
+        )?;
+        insert_pools(&transaction, &checkpoint.pools, Some(key.factory))?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO rejected_pools (address, factory, reason) VALUES (?1, ?2, ?3)
+                ON CONFLICT (address) DO UPDATE SET factory = excluded.factory,
+                reason = excluded.reason
+                WHERE (factory, reason) IS NOT (excluded.factory, excluded.reason)",
+            )?;
+            for (address, reason) in &checkpoint.rejected {
+                statement.execute(params![
+                    format!("{:?}", address),
+                    format!("{:?}", key.factory),
+                    serde_json::to_string(reason)?,
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn check_chain_id(chain_id: u64, key: &CheckpointKey) -> Result<(), DatabaseError> {
+    if chain_id != key.chain_id {
+        return Err(DatabaseError::ChainMismatch {
+            expected: chain_id,
+            found: key.chain_id,
+        });
+    }
+    Ok(())
+}
+
+/// Inserts `pools` and their tokens, updates the pools that changed, and appends their new
+/// reserves stamped with the current time. Pools without a factory, e.g. migrated from version 1
+/// checkpoints, are saved with `default_factory` when given.
+fn insert_pools(
+    connection: &Connection,
+    pools: &[UniswapV2Pool],
+    default_factory: Option<H160>,
+) -> Result<(), DatabaseError> {
+    let recorded_at = chrono::Utc::now().timestamp();
+    let mut pool_statement = connection.prepare_cached(
+        "INSERT INTO pools (address, factory, token_a, token_a_decimals, token_b,
+        token_b_decimals, fee, creation_block, creation_transaction, creation_log_index,
+        pair_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+        ON CONFLICT (address) DO UPDATE SET factory = excluded.factory,
+        token_a = excluded.token_a, token_a_decimals = excluded.token_a_decimals,
+        token_b = excluded.token_b, token_b_decimals = excluded.token_b_decimals,
+        fee = excluded.fee, creation_block = excluded.creation_block,
+        creation_transaction = excluded.creation_transaction,
+        creation_log_index = excluded.creation_log_index, pair_index = excluded.pair_index
+        WHERE (factory, token_a, token_a_decimals, token_b, token_b_decimals, fee,
+        creation_block, creation_transaction, creation_log_index, pair_index) IS NOT
+        (excluded.factory, excluded.token_a, excluded.token_a_decimals, excluded.token_b,
+        excluded.token_b_decimals, excluded.fee, excluded.creation_block,
+        excluded.creation_transaction, excluded.creation_log_index, excluded.pair_index)",
+    )?;
+    let mut token_statement = connection
+        .prepare_cached("INSERT OR IGNORE INTO tokens (address, decimals) VALUES (?1, ?2)")?;
+    let mut reserves_statement = connection.prepare_cached(
+        "INSERT OR IGNORE INTO reserves (pool, block_number, block_timestamp_last, recorded_at,
+        reserve_0, reserve_1) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for pool in pools {
+        let address = format!("{:?}", pool.address);
+        let factory = match default_factory {
+            Some(factory) if pool.factory.is_zero() => factory,
+            _ => pool.factory,
+        };
+        pool_statement.execute(params![
+            address,
+            format!("{:?}", factory),
+            format!("{:?}", pool.token_a),
+            pool.token_a_decimals,
+            format!("{:?}", pool.token_b),
+            pool.token_b_decimals,
+            pool.fee,
+            pool.creation.map(|creation| creation.block_number),
+            pool.creation
+                .map(|creation| format!("{:?}", creation.transaction_hash)),
+            pool.creation.map(|creation| creation.log_index),
+            pool.creation.map(|creation| creation.pair_index),
+        ])?;
+        token_statement.execute(params![
+            format!("{:?}", pool.token_a),
+            pool.token_a_decimals
+        ])?;
+        token_statement.execute(params![
+            format!("{:?}", pool.token_b),
+            pool.token_b_decimals
+        ])?;
+        reserves_statement.execute(params![
+            address,
+            pool.block_number,
+            pool.block_timestamp_last,
+            recorded_at,
+            pool.reserve_0.to_string(),
+            pool.reserve_1.to_string(),
+        ])?;
+    }
+    Ok(())
+}
+
+fn factory_pools(
+    connection: &Connection,
+    factory: H160,
+) -> Result<Vec<UniswapV2Pool>, DatabaseError> {
+    query_pools(
+        connection,
+        "WHERE p.factory = ?1 AND r.block_number =
+            (SELECT MAX(block_number) FROM reserves WHERE pool = p.address)
+        ORDER BY p.pair_index, p.address",
+        params![format!("{:?}", factory)],
+    )
+}
+
+fn query_pools(
+    connection: &Connection,
+    clause: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<UniswapV2Pool>, DatabaseError> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT {POOL_COLUMNS} FROM pools p JOIN reserves r ON r.pool = p.address {clause}"
+    ))?;
+    let rows = statement.query_map(params, PoolRow::from_row)?;
+    rows.map(|row| row?.into_pool()).collect()
+}
+
+/// Columns of a pool joined with one of its reserves, parsed once the row is read.
+struct PoolRow {
+    address: String,
+    factory: String,
+    token_a: String,
+    token_a_decimals: u8,
+    token_b: String,
+    token_b_decimals: u8,
+    fee: u32,
+    creation_block: Option<u64>,
+    creation_transaction: Option<String>,
+    creation_log_index: Option<u64>,
+    pair_index: Option<u64>,
+    reserve_0: String,
+    reserve_1: String,
+    block_timestamp_last: u32,
+    block_number: u64,
+}
+
+impl PoolRow {
+    fn from_row(row: &Row) -> rusqlite::Result<PoolRow> {
+        Ok(PoolRow {
+            address: row.get(0)?,
+            factory: row.get(1)?,
+            token_a: row.get(2)?,
+            token_a_decimals: row.get(3)?,
+            token_b: row.get(4)?,
+            token_b_decimals: row.get(5)?,
+            fee: row.get(6)?,
+            creation_block: row.get(7)?,
+            creation_transaction: row.get(8)?,
+            creation_log_index: row.get(9)?,
+            pair_index: row.get(10)?,
+            reserve_0: row.get(11)?,
+            reserve_1: row.get(12)?,
+            block_timestamp_last: row.get(13)?,
+            block_number: row.get(14)?,
+        })
+    }
+
+    fn into_pool(self) -> Result<UniswapV2Pool, DatabaseError> {
+        let creation = match (
+            self.creation_block,
+            self.creation_transaction,
+            self.creation_log_index,
+            self.pair_index,
+        ) {
+            (Some(block_number), Some(transaction_hash), Some(log_index), Some(pair_index)) => {
+                Some(PoolCreation {
+                    block_number,
+                    transaction_hash: parse::<H256>(&transaction_hash)?,
+                    log_index,
+                    pair_index,
+                })
+            }
+            _ => None,
+        };
+        Ok(UniswapV2Pool {
+            address: parse(&self.address)?,
+            token_a: parse(&self.token_a)?,
+            token_a_decimals: self.token_a_decimals,
+            token_b: parse(&self.token_b)?,
+            token_b_decimals: self.token_b_decimals,
+            reserve_0: parse(&self.reserve_0)?,
+            reserve_1: parse(&self.reserve_1)?,
+            block_timestamp_last: self.block_timestamp_last,
+            block_number: self.block_number,
+            fee: self.fee,
+            creation,
+            factory: parse(&self.factory)?,
+        })
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, DatabaseError> {
+    value
+        .parse()
+        .map_err(|_| DatabaseError::InvalidValue(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(address: u64, token_b: u64, reserve: u128, block_number: u64) -> UniswapV2Pool {
+        UniswapV2Pool {
+            address: H160::from_low_u64_be(address),
+            token_a: H160::from_low_u64_be(100),
+            token_b: H160::from_low_u64_be(token_b),
+            reserve_0: reserve,
+            reserve_1: u128::MAX,
+            block_number,
+            fee: 300,
+            factory: H160::from_low_u64_be(1000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pool_database() {
+        let database = PoolDatabase::open_in_memory().unwrap();
+        let key = CheckpointKey::new(1, H160::from_low_u64_be(1000));
+        let mut checkpoint = Checkpoint::new(
+            0,
+            10,
+            UniswapV2Factory::new(key.factory, 0, 300),
+            vec![pool(1, 101, 5, 10), pool(2, 102, 6, 10)],
+            vec![(H160::from_low_u64_be(3), RejectReason::MissingPoolCode)],
+        );
+        database.save(&key, &checkpoint).unwrap();
+        checkpoint.pools[0] = pool(1, 101, 7, 20);
+        checkpoint.block_number = 20;
+        database.save(&key, &checkpoint).unwrap();
+
+        let loaded = database.load(&key).unwrap().unwrap();
+        assert_eq!(loaded.block_number, 20);
+        assert_eq!(loaded.rejected, checkpoint.rejected);
+        let reserves: Vec<_> = loaded
+            .pools
+            .iter()
+            .map(|pool| (pool.reserve_0, pool.reserve_1, pool.block_number))
+            .collect();
+        assert_eq!(reserves, vec![(7, u128::MAX, 20), (6, u128::MAX, 10)]);
+
+        let with_token = database
+            .pools_with_token(H160::from_low_u64_be(102))
+            .unwrap();
+        assert_eq!(with_token.len(), 1);
+        let address = H160::from_low_u64_be(1);
+        assert_eq!(database.reserve_history(address).unwrap().len(), 2);
+        assert_eq!(
+            database
+                .pool_at_block(address, 15)
+                .unwrap()
+                .unwrap()
+                .reserve_0,
+            5
+        );
+
+        assert_eq!(
+            database
+                .token(H160::from_low_u64_be(101))
+                .unwrap()
+                .unwrap()
+                .decimals,
+            Some(0)
+        );
+        let token = Token {
+            address: H160::from_low_u64_be(101),
+            symbol: Some("ABC".to_string()),
+            decimals: Some(18),
+            ..Default::default()
+        };
+        database.save_tokens(&[token.clone()]).unwrap();
+        database.save(&key, &checkpoint).unwrap();
+        assert_eq!(database.token(token.address).unwrap(), Some(token));
+
+        // Saving again only writes the factory row
+        let total_changes = || -> i64 {
+            let connection = database.connection.lock().unwrap();
+            connection
+                .query_row("SELECT total_changes()", [], |row| row.get(0))
+                .unwrap()
+        };
+        let changes = total_changes();
+        database.save(&key, &checkpoint).unwrap();
+        assert_eq!(total_changes(), changes + 1);
+
+        let other_chain = CheckpointKey::new(10, key.factory);
+        for result in [
+            database.load(&other_chain).map(|_| ()),
+            database.save(&other_chain, &checkpoint),
+        ] {
+            assert!(matches!(
+                result,
+                Err(CheckpointError::DatabaseError(
+                    DatabaseError::ChainMismatch {
+                        expected: 1,
+                        found: 10
+                    }
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_save_pools_without_factory() {
+        let database = PoolDatabase::open_in_memory().unwrap();
+        let key = CheckpointKey::new(1, H160::from_low_u64_be(1000));
+        let migrated = UniswapV2Pool {
+            factory: H160::zero(),
+            ..pool(1, 101, 5, 10)
+        };
+        let checkpoint = Checkpoint::new(
+            0,
+            10,
+            UniswapV2Factory::new(key.factory, 0, 300),
+            vec![migrated],
+            vec![],
+        );
+        database.save(&key, &checkpoint).unwrap();
+
+        let loaded = database.load(&key).unwrap().unwrap();
+        assert_eq!(loaded.pools.len(), 1);
+        assert_eq!(loaded.pools[0].factory, key.factory);
+    }
+}
@@ -1,11 +1,14 @@
 use ethers::prelude::{AbiError, ContractError};
 use ethers::providers::{Middleware, ProviderError};
 use ethers::types::{H160, U256};
+use std::path::PathBuf;
 use std::time::SystemTimeError;
 use thiserror::Error;
 use tokio::task::JoinError;
 use uniswap_v3_math::error::UniswapV3MathError;
 
+use crate::uniswap_v2::batch_request::RejectReason;
+
 #[derive(Error, Debug)]
 pub enum AMMError<M>
 where
@@ -57,10 +60,29 @@ where
     SwapSimulationError(#[from] SwapSimulationError),
     #[error("Invalid data from batch request")]
     BatchRequestError(H160),
+    #[error("Invalid output from {0} batch request")]
+    InvalidBatchRequestOutput(&'static str),
+    #[error("The {0} batch request has no multicall equivalent")]
+    MulticallUnsupported(&'static str),
+    #[error("Not called as a call of the {0} batch request failed to reach the node")]
+    BatchRequestAborted(&'static str),
+    #[error("Pool rejected by batch request")]
+    PoolRejected(H160, RejectReason),
     #[error("Checkpoint error")]
     CheckpointError(#[from] CheckpointError),
     #[error("Out of gas error")]
     OutOfGasError(Vec<H160>),
+    #[error("Token cache error")]
+    TokenCacheError(#[from] TokenCacheError),
+    #[error("Batch request decode error")]
+    BatchDecodeError(#[from] BatchDecodeError),
+    #[error("State override error")]
+    StateOverrideError(#[from] StateOverrideError),
+    #[error("State space error")]
+    StateSpaceError(#[from] StateSpaceError),
+    #[cfg(feature = "sqlite")]
+    #[error("Database error")]
+    DatabaseError(#[from] DatabaseError),
 }
 
 #[derive(Error, Debug)]
@@ -85,6 +107,12 @@ pub enum EventLogError {
     InvalidEventSignature,
     #[error("Log Block number not found")]
     LogBlockNumberNotFound,
+    #[error("Log transaction hash not found")]
+    LogTransactionHashNotFound,
+    #[error("Log index not found")]
+    LogIndexNotFound,
+    #[error("Invalid pair index, allPairs length {0}")]
+    InvalidPairIndex(U256),
     #[error("Eth abi error")]
     EthABIError(#[from] ethers::abi::Error),
     #[error("ABI error")]
@@ -103,10 +131,72 @@ pub enum SwapSimulationError {
 
 #[derive(Error, Debug)]
 pub enum CheckpointError {
+    #[error("Checkpoint version {found} cannot be read, the supported version is {supported}")]
+    IncompatibleVersion { found: u32, supported: u32 },
+    #[error("Checkpoint does not match the schema of version {version}")]
+    InvalidSchema {
+        version: u32,
+        #[source]
+        source: serde_json::error::Error,
+    },
+    #[error("Not a binary checkpoint")]
+    InvalidBinaryHeader,
+    #[error("Checkpoint {0:?} does not match its checksum")]
+    ChecksumMismatch(PathBuf),
+    #[error("Checkpoint of factory {found:?} stored under the key of factory {expected:?}")]
+    FactoryMismatch { expected: H160, found: H160 },
     #[error("System time error")]
     SystemTimeError(#[from] SystemTimeError),
     #[error("Serde json error")]
     SerdeJsonError(#[from] serde_json::error::Error),
+    #[error("Bincode error")]
+    BincodeError(#[from] bincode::Error),
     #[error("IO error")]
     IOError(#[from] std::io::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("Database error")]
+    DatabaseError(#[from] DatabaseError),
+}
+
+#[derive(Error, Debug)]
+pub enum TokenCacheError {
+    #[error("Serde json error")]
+    SerdeJsonError(#[from] serde_json::error::Error),
+    #[error("IO error")]
+    IOError(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BatchDecodeError {
+    #[error("Invalid {field} in batch request output for {address:?}")]
+    InvalidField { address: H160, field: &'static str },
+}
+
+#[derive(Error, Debug)]
+pub enum StateOverrideError {
+    #[error("Reserve {0} does not fit in a uint112")]
+    ReserveOverflow(u128),
+}
+
+#[derive(Error, Debug)]
+pub enum StateSpaceError {
+    #[error("Block {0} not found")]
+    BlockNotFound(u64),
+    #[error("Block {0} has no hash")]
+    PendingBlock(u64),
+    #[error("Reorg at block {0} is deeper than the state change history")]
+    ReorgBeyondHistory(u64),
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("SQLite error")]
+    SqliteError(#[from] rusqlite::Error),
+    #[error("Serde json error")]
+    SerdeJsonError(#[from] serde_json::error::Error),
+    #[error("Invalid value {0} in database")]
+    InvalidValue(String),
+    #[error("Database holds the pools of chain {expected}, not chain {found}")]
+    ChainMismatch { expected: u64, found: u64 },
 }
@@ -1,5 +1,13 @@
+pub mod batch_request;
 pub mod configs;
+#[cfg(feature = "sqlite")]
+pub mod database;
 pub mod errors;
 mod large_int_maths;
+pub mod multicall;
 pub mod playground;
+pub mod state_override;
+#[cfg(feature = "state-space")]
+pub mod state_space;
+pub mod tokens;
 pub mod uniswap_v2;
@@ -4,11 +4,17 @@ use amm_toolkit::playground;
 async fn main() {
     dotenv::dotenv().ok();
     // playground::simulate_swaps().await.unwrap();
+    // playground::simulate_swaps_with_overridden_reserves().await.unwrap();
     // playground::get_usdc_weth_price().await.unwrap();
     // playground::get_swap_call_data().await.unwrap();
     // playground::get_pools_from_log().await.unwrap();
     // playground::get_all_pools().await.unwrap();
+    // playground::stream_all_pools(0).await.unwrap();
     // playground::run_sync_uniswap_v2_pools().await.unwrap();
+    // playground::run_sync_uniswap_v2_pools_from_factories().await.unwrap();
+    // playground::follow_pool_state_changes().await.unwrap();
+    // playground::stream_reserve_updates().await.unwrap();
+    // playground::sync_uniswap_v2_pools_into_database().await.unwrap();
     playground::get_top_pools_in_terms_of_weth_equivalent_value(20)
         .await
         .unwrap();
@@ -0,0 +1,148 @@
+//! Multicall3 `aggregate3` backend for batch requests, for RPC providers and L2 nodes that cap
+//! the `eth_call` init code size or reject contract creation calls.
+
+use std::sync::{Arc, Mutex};
+
+use ethers::{
+    abi::{Function, Token},
+    prelude::abigen,
+    providers::{Middleware, RawCall},
+    types::{spoof, BlockId, Bytes, H160, U256},
+};
+
+use crate::{batch_request::CallTelemetry, errors::AMMError};
+
+abigen!(
+    IMulticall3,
+    r#"[
+        struct Call3 { address target; bool allowFailure; bytes callData; }
+        struct Call3Result { bool success; bytes returnData; }
+        function aggregate3(Call3[] calldata calls) external payable returns (Call3Result[] memory returnData)
+    ]"#;
+);
+
+/// Multicall3 is deployed at 0xcA11bde05977b3631167028862bE2a173976CA11 on every chain it
+/// supports.
+pub const MULTICALL3_ADDRESS: H160 = H160([
+    202, 17, 189, 224, 89, 119, 179, 99, 17, 103, 2, 136, 98, 190, 42, 23, 57, 118, 202, 17,
+]);
+
+/// A single call of an `aggregate3` batch, its failure never reverts the batch.
+#[derive(Debug, Clone)]
+pub struct MulticallCall {
+    pub target: H160,
+    pub call_data: Bytes,
+}
+
+impl MulticallCall {
+    pub fn new(
+        target: H160,
+        function: &Function,
+        args: &[Token],
+    ) -> Result<Self, ethers::abi::Error> {
+        Ok(MulticallCall {
+            target,
+            call_data: function.encode_input(args)?.into(),
+        })
+    }
+}
+
+/// Outcome of a single call, `success` is false when the call reverted.
+#[derive(Debug, Clone)]
+pub struct MulticallResult {
+    pub success: bool,
+    pub return_data: Bytes,
+}
+
+impl MulticallResult {
+    /// Decodes the return data of a successful call, `None` when it reverted or returned
+    /// something else than the expected outputs, e.g. nothing because `target` has no code.
+    pub fn decode(&self, function: &Function) -> Option<Vec<Token>> {
+        if !self.success {
+            return None;
+        }
+        function.decode_output(&self.return_data).ok()
+    }
+
+    /// The return data of a successful call returning exactly one word, read as a `uint256` so
+    /// that out of range values of narrower types can be detected.
+    pub fn decode_word(&self) -> Option<U256> {
+        if !self.success || self.return_data.len() != 32 {
+            return None;
+        }
+        Some(U256::from_big_endian(&self.return_data))
+    }
+
+    /// A call to an address without code succeeds but returns no data.
+    pub fn is_empty_success(&self) -> bool {
+        self.success && self.return_data.is_empty()
+    }
+}
+
+/// Runs `calls` through `aggregate3` with `allowFailure` set, returning one result per call.
+/// `state` overrides are applied to the call when set. When `telemetry` is set, the call is
+/// measured with an extra `eth_estimateGas`, counting each of `calls` as an input.
+pub async fn aggregate3<M: Middleware>(
+    calls: Vec<MulticallCall>,
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+    state: Option<&spoof::State>,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+) -> Result<Vec<MulticallResult>, AMMError<M>> {
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, middleware.clone());
+    let calls: Vec<Call3> = calls
+        .into_iter()
+        .map(|call| Call3 {
+            target: call.target,
+            allow_failure: true,
+            call_data: call.call_data,
+        })
+        .collect();
+
+    let expected = calls.len();
+    let mut aggregate = multicall.aggregate_3(calls);
+    if let Some(block) = block {
+        aggregate = aggregate.block(block);
+    }
+    let call = aggregate.call_raw();
+    let results: Vec<MulticallResult> = match state {
+        Some(state) => call.state(state).await?,
+        None => call.await?,
+    }
+    .into_iter()
+    .map(|(success, return_data)| MulticallResult {
+        success,
+        return_data,
+    })
+    .collect();
+
+    if results.len() != expected {
+        return Err(AMMError::InvalidBatchRequestOutput("multicall3"));
+    }
+
+    if let Some(telemetry) = telemetry {
+        // State overrides are not supported by eth_estimateGas, the estimate runs without them
+        let gas_used = middleware
+            .estimate_gas(&aggregate.tx, block)
+            .await
+            .ok()
+            .map(|gas| gas.as_u64());
+        let response = Token::Array(
+            results
+                .iter()
+                .map(|result| {
+                    Token::Tuple(vec![
+                        Token::Bool(result.success),
+                        Token::Bytes(result.return_data.to_vec()),
+                    ])
+                })
+                .collect(),
+        );
+        telemetry.lock().unwrap().push(CallTelemetry {
+            inputs: expected,
+            gas_used,
+            response_size: ethers::abi::encode(&[response]).len(),
+        });
+    }
+    Ok(results)
+}
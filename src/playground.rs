@@ -1,10 +1,18 @@
-use ethers::types::{H160, U256};
-use std::str::FromStr;
+use ethers::types::{spoof, H160, U256};
+use futures::StreamExt;
+use std::{collections::HashMap, str::FromStr};
 
 use crate::{
     configs::Config,
+    tokens::TokenCache,
     uniswap_v2::{
-        batch_request::get_weth_value_in_pools, sync::sync_uniswap_v2_pools, UniswapV2Pool,
+        batch_request::get_weth_value_in_pools,
+        checkpoint::FileCheckpointStore,
+        factory::UniswapV2Factory,
+        router::get_amounts_out,
+        subscription::subscribe_sync_events,
+        sync::{sync_uniswap_v2_pools, sync_uniswap_v2_pools_from_factories},
+        UniswapV2Pool,
     },
 };
 
@@ -24,6 +32,34 @@ pub async fn simulate_swaps() -> eyre::Result<()> {
     Ok(())
 }
 
+/// Compares `simulate_swap` with the router quote after doubling the reserves of the pool through
+/// state overrides.
+pub async fn simulate_swaps_with_overridden_reserves() -> eyre::Result<()> {
+    let config = Config::new()?;
+    let mut pool = config.pool("WETH", "USDc").await?;
+    pool.reserve_0 *= 2;
+    pool.reserve_1 *= 2;
+    let mut state = spoof::state();
+    pool.override_reserves(&mut state)?;
+
+    let amount_in = U256::from_dec_str("1000000000000000000")?;
+    let amounts = get_amounts_out(
+        H160::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")?,
+        amount_in,
+        vec![config.tokens["WETH"], pool.token_a],
+        config.middleware,
+        None,
+        Some(&state),
+    )
+    .await?;
+    println!(
+        "Simulated: {}, router: {}",
+        pool.simulate_swap(config.tokens["WETH"], amount_in)?,
+        amounts[1]
+    );
+    Ok(())
+}
+
 pub async fn get_usdc_weth_price() -> eyre::Result<()> {
     let config = Config::new()?;
     let price = config
@@ -66,40 +102,182 @@ pub async fn get_all_pools() -> eyre::Result<()> {
     let config = Config::new()?;
     let pools = config
         .uniswap_v2_factory
-        .get_all_pools(config.middleware, None)
+        .get_all_pools(config.middleware, None, None)
+        .await?;
+    println!("Got {:?}, rejected {:?}", pools.0.len(), pools.1.len());
+    Ok(())
+}
+
+pub async fn stream_all_pools(from_index: u128) -> eyre::Result<()> {
+    let config = Config::new()?;
+    let (pool_batches, pairs_length) = config
+        .uniswap_v2_factory
+        .stream_all_pools(config.middleware.clone(), Some(from_index), None, None)
         .await?;
-    println!("Got {:?}", pools.0.len());
+    futures::pin_mut!(pool_batches);
+    while let Some(pool_batch) = pool_batches.next().await {
+        let pool_batch = pool_batch?;
+        println!(
+            "Got {} pools, resume from index {} of {}",
+            pool_batch.pools.len(),
+            pool_batch.to,
+            pairs_length
+        );
+    }
     Ok(())
 }
 
 pub async fn run_sync_uniswap_v2_pools() -> eyre::Result<()> {
     let config = Config::new()?;
-    let pools = sync_uniswap_v2_pools(config.uniswap_v2_factory, config.middleware).await?;
-    println!("Got {:?}", pools.len());
+    let (pools, rejected, stale) = sync_uniswap_v2_pools(
+        config.uniswap_v2_factory,
+        config.middleware,
+        &FileCheckpointStore::default(),
+    )
+    .await?;
+    println!(
+        "Got {:?}, rejected {:?}, stale {:?}",
+        pools.len(),
+        rejected.len(),
+        stale.len()
+    );
+    Ok(())
+}
+
+pub async fn run_sync_uniswap_v2_pools_from_factories() -> eyre::Result<()> {
+    let config = Config::new()?;
+    let sushiswap_factory = UniswapV2Factory::new(
+        H160::from_str("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac")?,
+        10794229,
+        300,
+    );
+    let synced = sync_uniswap_v2_pools_from_factories(
+        vec![config.uniswap_v2_factory, sushiswap_factory],
+        config.middleware,
+        &FileCheckpointStore::default(),
+    )
+    .await;
+    for summary in synced.summaries {
+        println!("{:?}", summary);
+    }
+    println!("Got {:?}", synced.pools.len());
     Ok(())
 }
 
 pub async fn get_top_pools_in_terms_of_weth_equivalent_value(top: usize) -> eyre::Result<()> {
     let config = Config::new()?;
-    let pools =
-        sync_uniswap_v2_pools(config.uniswap_v2_factory.clone(), config.middleware.clone()).await?;
-    let pool_addresses = pools.into_iter().map(|pool| pool.address).collect();
-    let map = get_weth_value_in_pools(
+    let (pools, _, _) = sync_uniswap_v2_pools(
+        config.uniswap_v2_factory.clone(),
+        config.middleware.clone(),
+        &FileCheckpointStore::default(),
+    )
+    .await?;
+    let pool_addresses = pools.iter().map(|pool| pool.address).collect();
+    let (map, failed) = get_weth_value_in_pools(
         pool_addresses,
         config.tokens["WETH"],
         config.uniswap_v2_factory.address,
         config.middleware.clone(),
         Some(50),
+        None,
     )
     .await?;
+    println!("Could not value {} pools", failed.len());
 
     let mut entries: Vec<_> = map.iter().collect();
     entries.sort_by(|a, b| b.1.cmp(a.1)); // Sort by value in descending order
     let top: Vec<_> = entries.into_iter().take(top).collect();
 
-    for (key, value) in top {
+    let pools: HashMap<H160, UniswapV2Pool> =
+        pools.into_iter().map(|pool| (pool.address, pool)).collect();
+    let top_pools: Vec<UniswapV2Pool> = top.iter().map(|(key, _)| pools[*key].clone()).collect();
+    let mut token_cache = config.token_cache.clone();
+    token_cache
+        .fetch_missing_for_pools(&top_pools, config.middleware.clone(), None)
+        .await?;
+    token_cache.save_to_path(&config.token_cache_path)?;
+
+    for ((key, value), pool) in top.into_iter().zip(&top_pools) {
         let eth_value = value / U256::exp10(18);
-        println!("{:?}: {:?}", key, eth_value);
+        println!(
+            "{} {:?}: {:?}",
+            token_cache.pool_symbols(pool),
+            key,
+            eth_value
+        );
+    }
+    Ok(())
+}
+
+/// Streams the reserve updates of the WETH/USDC pool over the WebSocket endpoint in `NETWORK_WS_RPC`.
+pub async fn stream_reserve_updates() -> eyre::Result<()> {
+    let config = Config::new()?;
+    let mut pool = config.pool("WETH", "USDc").await?;
+    let (mut updates, handle) = subscribe_sync_events(
+        std::env::var("NETWORK_WS_RPC")?,
+        vec![pool.address],
+        None,
+        None,
+    );
+    while let Some(update) = updates.recv().await {
+        update.apply(&mut pool);
+        println!(
+            "Block {}: reserves {} / {}",
+            update.block_number, pool.reserve_0, pool.reserve_1
+        );
+    }
+    handle.await??;
+    Ok(())
+}
+
+/// Follows the reserves of the WETH/USDC pool, printing them on every block where they change.
+#[cfg(feature = "state-space")]
+pub async fn follow_pool_state_changes() -> eyre::Result<()> {
+    use crate::state_space::StateSpaceManager;
+    use ethers::providers::Middleware;
+
+    let config = Config::new()?;
+    let pool = config.pool("WETH", "USDc").await?;
+    let block_number = config.middleware.get_block_number().await?.as_u64();
+    let manager = StateSpaceManager::new(vec![pool], block_number, config.middleware);
+    let state = manager.state();
+    let (mut changes, handle) = manager.subscribe_state_changes(100);
+    while let Some(change) = changes.recv().await {
+        let state = state.read().await;
+        for address in change.changed {
+            let pool = &state[&address];
+            println!(
+                "Block {}: {:?} reserves {} / {}",
+                change.block_number, address, pool.reserve_0, pool.reserve_1
+            );
+        }
     }
+    handle.await??;
+    Ok(())
+}
+
+/// Syncs the Uniswap V2 pools into an SQLite database and lists the pools of WETH, saving the
+/// metadata of their tokens.
+#[cfg(feature = "sqlite")]
+pub async fn sync_uniswap_v2_pools_into_database() -> eyre::Result<()> {
+    use crate::database::PoolDatabase;
+
+    let config = Config::new()?;
+    std::fs::create_dir_all("checkpoint_data")?;
+    let database = PoolDatabase::open("checkpoint_data/uniswap_v2.sqlite")?;
+    sync_uniswap_v2_pools(
+        config.uniswap_v2_factory,
+        config.middleware.clone(),
+        &database,
+    )
+    .await?;
+    let weth_pools = database.pools_with_token(config.tokens["WETH"])?;
+    println!("Got {} WETH pools", weth_pools.len());
+
+    let mut token_cache = TokenCache::new();
+    token_cache
+        .fetch_missing_for_pools(&weth_pools, config.middleware, None)
+        .await?;
+    database.save_tokens(&token_cache.tokens.into_values().collect::<Vec<_>>())?;
     Ok(())
 }
@@ -0,0 +1,53 @@
+//! Helpers building `eth_call` state override sets, to run batch requests and quotes against
+//! hypothetical state. Overrides are applied as a diff on the existing account storage.
+
+use ethers::{
+    abi::{encode, Token},
+    types::{spoof, Bytes, H160, H256, U256},
+    utils::keccak256,
+};
+
+/// Storage slot of `balanceOf[holder]` for a token keeping its balances in a
+/// `mapping(address => uint256)` at slot `balances_slot`, e.g. slot 3 for WETH.
+pub fn erc20_balance_slot(holder: H160, balances_slot: U256) -> H256 {
+    H256(keccak256(encode(&[
+        Token::Address(holder),
+        Token::Uint(balances_slot),
+    ])))
+}
+
+/// Overrides the token balance of `holder`.
+pub fn override_erc20_balance(
+    state: &mut spoof::State,
+    token: H160,
+    holder: H160,
+    balances_slot: U256,
+    balance: U256,
+) {
+    let mut value = [0u8; 32];
+    balance.to_big_endian(&mut value);
+    state
+        .account(token)
+        .store(erc20_balance_slot(holder, balances_slot), H256(value));
+}
+
+/// Replaces the code at `address`, e.g. to mock a token or a pair.
+pub fn override_code(state: &mut spoof::State, address: H160, code: Bytes) {
+    state.account(address).code(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_erc20_balance_slot() {
+        // keccak256(abi.encode(address(0), uint256(0)))
+        assert_eq!(
+            erc20_balance_slot(H160::zero(), U256::zero()),
+            H256::from_str("0xad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5")
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use arraydeque::{ArrayDeque, Wrapping};
+use ethers::types::{H160, H256};
+
+use super::StateSpace;
+use crate::uniswap_v2::UniswapV2Pool;
+
+/// Number of blocks that can be rolled back after a reorg.
+pub const STATE_CHANGE_HISTORY: usize = 150;
+
+/// Diff applied to the state space by a block, holding the tracked pools it updated as they were
+/// before the block.
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub parent_hash: H256,
+    pub previous: Vec<UniswapV2Pool>,
+}
+
+/// Bounded history of the last [`STATE_CHANGE_HISTORY`] synced blocks, oldest blocks are dropped
+/// first.
+#[derive(Debug, Default)]
+pub struct StateChangeCache {
+    changes: ArrayDeque<StateChange, STATE_CHANGE_HISTORY, Wrapping>,
+}
+
+impl StateChangeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, change: StateChange) {
+        self.changes.push_back(change);
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Blocks in the history from the newest to the oldest, as `(block_number, block_hash)`.
+    pub fn blocks(&self) -> impl Iterator<Item = (u64, H256)> + '_ {
+        self.changes
+            .iter()
+            .rev()
+            .map(|change| (change.block_number, change.block_hash))
+    }
+
+    /// Whether a block with `parent_hash` extends the last synced block. Always true for an empty
+    /// history or when the last synced block is not the parent height, as there is nothing to
+    /// compare against.
+    pub fn is_continuation(&self, block_number: u64, parent_hash: H256) -> bool {
+        match self.changes.back() {
+            Some(last) if last.block_number + 1 == block_number => last.block_hash == parent_hash,
+            _ => true,
+        }
+    }
+
+    /// Reverts every block after `ancestor_block`, restoring the pools they updated, and returns
+    /// the addresses of the restored pools.
+    pub fn unwind_to(&mut self, state: &mut StateSpace, ancestor_block: u64) -> Vec<H160> {
+        let mut reverted = vec![];
+        while self
+            .changes
+            .back()
+            .is_some_and(|change| change.block_number > ancestor_block)
+        {
+            let change = self.changes.pop_back().expect("back was just checked");
+            for pool in change.previous {
+                if !reverted.contains(&pool.address) {
+                    reverted.push(pool.address);
+                }
+                state.insert(pool.address, pool);
+            }
+        }
+        reverted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(address: u64, reserve: u128, block_number: u64) -> UniswapV2Pool {
+        UniswapV2Pool {
+            address: H160::from_low_u64_be(address),
+            reserve_0: reserve,
+            reserve_1: reserve,
+            block_number,
+            ..Default::default()
+        }
+    }
+
+    fn change(block_number: u64, previous: Vec<UniswapV2Pool>) -> StateChange {
+        StateChange {
+            block_number,
+            block_hash: H256::from_low_u64_be(block_number),
+            parent_hash: H256::from_low_u64_be(block_number - 1),
+            previous,
+        }
+    }
+
+    #[test]
+    fn test_unwind_to_common_ancestor() {
+        let mut cache = StateChangeCache::new();
+        cache.push(change(11, vec![pool(1, 100, 10)]));
+        cache.push(change(12, vec![pool(1, 110, 11), pool(2, 200, 10)]));
+        cache.push(change(13, vec![pool(2, 210, 12)]));
+        let mut state = StateSpace::from([
+            (H160::from_low_u64_be(1), pool(1, 120, 12)),
+            (H160::from_low_u64_be(2), pool(2, 220, 13)),
+        ]);
+
+        assert!(cache.is_continuation(14, H256::from_low_u64_be(13)));
+        assert!(!cache.is_continuation(14, H256::repeat_byte(1)));
+
+        let reverted = cache.unwind_to(&mut state, 11);
+
+        assert_eq!(
+            reverted,
+            vec![H160::from_low_u64_be(2), H160::from_low_u64_be(1)]
+        );
+        assert_eq!(cache.blocks().next(), Some((11, H256::from_low_u64_be(11))));
+        let pool_1 = &state[&H160::from_low_u64_be(1)];
+        let pool_2 = &state[&H160::from_low_u64_be(2)];
+        assert_eq!((pool_1.reserve_0, pool_1.block_number), (110, 11));
+        assert_eq!((pool_2.reserve_0, pool_2.block_number), (200, 10));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut cache = StateChangeCache::new();
+        for block_number in 1..=STATE_CHANGE_HISTORY as u64 + 10 {
+            cache.push(change(block_number, vec![]));
+        }
+
+        assert_eq!(cache.len(), STATE_CHANGE_HISTORY);
+        assert_eq!(cache.blocks().last().map(|(number, _)| number), Some(11));
+    }
+}
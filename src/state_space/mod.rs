@@ -0,0 +1,267 @@
+pub mod cache;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use ethers::{
+    providers::Middleware,
+    types::{Filter, Log, H160, H256},
+};
+use tokio::{
+    sync::{mpsc, RwLock},
+    task::JoinHandle,
+};
+
+use self::cache::{StateChange, StateChangeCache};
+use crate::{
+    errors::{AMMError, EventLogError, StateSpaceError},
+    uniswap_v2::{UniswapV2Pool, SYNC_EVENT_SIGNATURE},
+};
+
+pub type StateSpace = HashMap<H160, UniswapV2Pool>;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pools whose reserves were updated by the `Sync` logs of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockStateChange {
+    pub block_number: u64,
+    pub changed: Vec<H160>,
+}
+
+/// Keeps the reserves of a set of pools up to date by following new blocks and applying the
+/// `Sync` logs emitted by the tracked pools. The changes of the recent blocks are kept to roll
+/// the pools back when a reorg replaces those blocks.
+pub struct StateSpaceManager<M: Middleware> {
+    state: Arc<RwLock<StateSpace>>,
+    middleware: Arc<M>,
+    last_synced_block: u64,
+    cache: StateChangeCache,
+    pub poll_interval: Duration,
+}
+
+impl<M: Middleware + 'static> StateSpaceManager<M> {
+    /// `last_synced_block` is the block the reserves of `pools` were read at, following starts
+    /// from the next block.
+    pub fn new(pools: Vec<UniswapV2Pool>, last_synced_block: u64, middleware: Arc<M>) -> Self {
+        let state = pools.into_iter().map(|pool| (pool.address, pool)).collect();
+        StateSpaceManager {
+            state: Arc::new(RwLock::new(state)),
+            middleware,
+            last_synced_block,
+            cache: StateChangeCache::new(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Shared handle to the tracked pools, still valid once the manager is moved into
+    /// [`StateSpaceManager::subscribe_state_changes`].
+    pub fn state(&self) -> Arc<RwLock<StateSpace>> {
+        self.state.clone()
+    }
+
+    pub fn last_synced_block(&self) -> u64 {
+        self.last_synced_block
+    }
+
+    /// Syncs the block after the last synced one. If that block does not extend the last synced
+    /// block, the pools are first rolled back to the common ancestor with the canonical chain and
+    /// the canonical blocks are replayed from there, the rolled back pools are then reported as
+    /// changed by the synced block.
+    pub async fn sync_next_block(&mut self) -> Result<BlockStateChange, AMMError<M>> {
+        let mut reverted = vec![];
+        loop {
+            let block_number = self.last_synced_block + 1;
+            let (block_hash, parent_hash, timestamp) = self.block_header(block_number).await?;
+            if !self.cache.is_continuation(block_number, parent_hash) {
+                let ancestor_block = self.common_ancestor(block_number).await?;
+                reverted.extend(
+                    self.cache
+                        .unwind_to(&mut *self.state.write().await, ancestor_block),
+                );
+                self.last_synced_block = ancestor_block;
+                continue;
+            }
+
+            // Filtering on the topic only and discarding untracked pools locally keeps the
+            // request small regardless of the number of tracked pools. Pinning the block hash
+            // guarantees the logs belong to the block whose hash is recorded.
+            let filter = Filter::new()
+                .topic0(SYNC_EVENT_SIGNATURE)
+                .at_block_hash(block_hash);
+            let logs = self
+                .middleware
+                .get_logs(&filter)
+                .await
+                .map_err(AMMError::MiddlewareError)?;
+            let mut state = self.state.write().await;
+            let previous = apply_sync_logs(&mut state, logs)?;
+            // The logs do not carry `blockTimestampLast`, it is the timestamp of their block
+            for pool in previous.iter() {
+                if let Some(pool) = state.get_mut(&pool.address) {
+                    pool.block_timestamp_last = timestamp;
+                }
+            }
+            drop(state);
+
+            let mut changed: Vec<H160> = previous.iter().map(|pool| pool.address).collect();
+            for address in reverted {
+                if !changed.contains(&address) {
+                    changed.push(address);
+                }
+            }
+            self.cache.push(StateChange {
+                block_number,
+                block_hash,
+                parent_hash,
+                previous,
+            });
+            self.last_synced_block = block_number;
+            return Ok(BlockStateChange {
+                block_number,
+                changed,
+            });
+        }
+    }
+
+    /// Syncs every block after the last synced one up to the latest block.
+    pub async fn sync_to_latest_block(&mut self) -> Result<Vec<BlockStateChange>, AMMError<M>> {
+        let latest_block = self
+            .middleware
+            .get_block_number()
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .as_u64();
+        let mut changes = vec![];
+        while self.last_synced_block < latest_block {
+            changes.push(self.sync_next_block().await?);
+        }
+        Ok(changes)
+    }
+
+    /// Newest block of the history that is still part of the canonical chain.
+    async fn common_ancestor(&self, reorged_block: u64) -> Result<u64, AMMError<M>> {
+        for (block_number, block_hash) in self.cache.blocks() {
+            if self.block_header(block_number).await?.0 == block_hash {
+                return Ok(block_number);
+            }
+        }
+        Err(StateSpaceError::ReorgBeyondHistory(reorged_block).into())
+    }
+
+    /// Hash, parent hash and timestamp of the block.
+    async fn block_header(&self, block_number: u64) -> Result<(H256, H256, u32), AMMError<M>> {
+        let block = self
+            .middleware
+            .get_block(block_number)
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .ok_or(StateSpaceError::BlockNotFound(block_number))?;
+        let block_hash = block
+            .hash
+            .ok_or(StateSpaceError::PendingBlock(block_number))?;
+        Ok((block_hash, block.parent_hash, block.timestamp.low_u32()))
+    }
+
+    /// Follows new blocks in a background task, sending the pools changed by each block on the
+    /// returned channel. The task stops when the receiver is dropped or on the first error.
+    pub fn subscribe_state_changes(
+        mut self,
+        buffer: usize,
+    ) -> (
+        mpsc::Receiver<BlockStateChange>,
+        JoinHandle<Result<(), AMMError<M>>>,
+    ) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.poll_interval);
+            loop {
+                interval.tick().await;
+                for change in self.sync_to_latest_block().await? {
+                    if sender.send(change).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        });
+        (receiver, handle)
+    }
+}
+
+/// Applies `logs` in chain order to the pools of `state`, ignoring logs of untracked pools and
+/// logs removed by a reorg. Returns the updated pools as they were before the logs, in the order
+/// they were first updated. `block_timestamp_last` is left untouched, see
+/// [`UniswapV2Pool::sync_from_log`].
+pub fn apply_sync_logs(
+    state: &mut StateSpace,
+    mut logs: Vec<Log>,
+) -> Result<Vec<UniswapV2Pool>, EventLogError> {
+    logs.sort_by_key(|log| (log.block_number, log.log_index));
+    let mut previous: Vec<UniswapV2Pool> = vec![];
+    for log in logs {
+        if log.removed == Some(true) {
+            continue;
+        }
+        if let Some(pool) = state.get_mut(&log.address) {
+            if !previous
+                .iter()
+                .any(|previous| previous.address == pool.address)
+            {
+                previous.push(pool.clone());
+            }
+            pool.sync_from_log(log)?;
+        }
+    }
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, Token},
+        types::U256,
+    };
+
+    use super::*;
+
+    fn sync_log(address: H160, log_index: u64, reserve_0: u64, reserve_1: u64) -> Log {
+        Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])
+            .into(),
+            block_number: Some(10.into()),
+            log_index: Some(log_index.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_apply_sync_logs() {
+        let tracked = H160::from_low_u64_be(1);
+        let mut state = StateSpace::from([(
+            tracked,
+            UniswapV2Pool {
+                address: tracked,
+                ..Default::default()
+            },
+        )]);
+        let logs = vec![
+            sync_log(tracked, 3, 30, 31),
+            sync_log(H160::from_low_u64_be(2), 2, 20, 21),
+            sync_log(tracked, 1, 10, 11),
+        ];
+
+        let previous = apply_sync_logs(&mut state, logs).unwrap();
+
+        assert_eq!(previous.len(), 1);
+        assert_eq!((previous[0].address, previous[0].reserve_0), (tracked, 0));
+        let pool = &state[&tracked];
+        assert_eq!(
+            (pool.reserve_0, pool.reserve_1, pool.block_number),
+            (30, 31, 10)
+        );
+    }
+}
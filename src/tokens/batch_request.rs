@@ -0,0 +1,212 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use ethers::{
+    abi::{Abi, ParamType, Token as AbiToken},
+    prelude::abigen,
+    providers::Middleware,
+    types::{BlockId, Bytes, H160},
+};
+
+use crate::{
+    batch_request::{execute_batch_request, BatchRequest, BatchRequestOptions, CallTelemetry},
+    errors::{AMMError, BatchDecodeError},
+    multicall::{aggregate3, MulticallCall, MulticallResult},
+};
+
+use super::{Token, IERC20METADATA_ABI};
+
+abigen!(
+    IGetERC20TokenMetadataBatchRequest,
+    "src/contracts/GetERC20TokenMetadataBatchRequest.json";
+);
+
+/// Functions called on every token, in the order of the fields of the batch contract.
+const METADATA_FUNCTIONS: [&str; 4] = ["symbol", "name", "decimals", "totalSupply"];
+
+/// Fetches the metadata of ERC20 tokens. Outputs are `None` for addresses without code, fields
+/// of tokens that revert or return something unexpected are left unset.
+pub struct ERC20TokenMetadataBatchRequest;
+
+#[async_trait]
+impl BatchRequest for ERC20TokenMetadataBatchRequest {
+    type Input = H160;
+    type Output = Option<Token>;
+
+    fn name(&self) -> &'static str {
+        "erc20 token metadata"
+    }
+
+    fn abi(&self) -> &Abi {
+        &IGETERC20TOKENMETADATABATCHREQUEST_ABI
+    }
+
+    fn bytecode(&self) -> &Bytes {
+        &IGETERC20TOKENMETADATABATCHREQUEST_BYTECODE
+    }
+
+    fn constructor_args(&self, inputs: &[H160]) -> Vec<AbiToken> {
+        vec![AbiToken::Array(
+            inputs
+                .iter()
+                .map(|&address| AbiToken::Address(address))
+                .collect(),
+        )]
+    }
+
+    fn return_type(&self) -> ParamType {
+        ParamType::Tuple(vec![
+            ParamType::Bool,  // has code
+            ParamType::Bytes, // symbol
+            ParamType::Bytes, // name
+            ParamType::Bytes, // decimals
+            ParamType::Bytes, // total supply
+        ])
+    }
+
+    fn decode(&self, input: &H160, token: AbiToken) -> Result<Option<Token>, BatchDecodeError> {
+        let invalid_field = |field| BatchDecodeError::InvalidField {
+            address: *input,
+            field,
+        };
+        let fields = token.into_tuple().ok_or(invalid_field("token metadata"))?;
+        let [has_code, symbol, name, decimals, total_supply] =
+            <[AbiToken; 5]>::try_from(fields).map_err(|_| invalid_field("token metadata"))?;
+
+        if !has_code.into_bool().ok_or(invalid_field("has code"))? {
+            return Ok(None);
+        }
+        Ok(Some(Token::from_return_data(
+            *input,
+            &symbol.into_bytes().ok_or(invalid_field("symbol"))?,
+            &name.into_bytes().ok_or(invalid_field("name"))?,
+            &decimals.into_bytes().ok_or(invalid_field("decimals"))?,
+            &total_supply
+                .into_bytes()
+                .ok_or(invalid_field("total supply"))?,
+        )))
+    }
+
+    async fn multicall<M: Middleware>(
+        &self,
+        inputs: &[H160],
+        middleware: Arc<M>,
+        options: &BatchRequestOptions,
+        telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Option<Result<Vec<Option<Token>>, AMMError<M>>> {
+        Some(token_metadata_multicall(inputs, middleware, options, telemetry).await)
+    }
+}
+
+async fn token_metadata_multicall<M: Middleware>(
+    inputs: &[H160],
+    middleware: Arc<M>,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+) -> Result<Vec<Option<Token>>, AMMError<M>> {
+    let mut calls = Vec::with_capacity(inputs.len() * METADATA_FUNCTIONS.len());
+    for &address in inputs {
+        for name in METADATA_FUNCTIONS {
+            calls.push(MulticallCall::new(
+                address,
+                IERC20METADATA_ABI.function(name)?,
+                &[],
+            )?);
+        }
+    }
+
+    Ok(aggregate3(
+        calls,
+        middleware,
+        options.block,
+        options.state.as_ref(),
+        telemetry,
+    )
+    .await?
+    .chunks(METADATA_FUNCTIONS.len())
+    .zip(inputs)
+    .map(|(results, &address)| {
+        // Calls to an address without code all succeed without returning anything
+        if results.iter().all(MulticallResult::is_empty_success) {
+            return None;
+        }
+        let return_data = |index: usize| -> &[u8] {
+            match results.get(index) {
+                Some(result) if result.success => &result.return_data,
+                _ => &[],
+            }
+        };
+        Some(Token::from_return_data(
+            address,
+            return_data(0),
+            return_data(1),
+            return_data(2),
+            return_data(3),
+        ))
+    })
+    .collect())
+}
+
+/// Fetches the metadata of every token at `block`, skipping the addresses without code. Tokens
+/// whose metadata could not be decoded are returned separately.
+pub async fn get_token_metadata_batch_request<M: Middleware>(
+    token_addresses: &[H160],
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+) -> Result<(Vec<Token>, Vec<(H160, BatchDecodeError)>), AMMError<M>> {
+    let options = BatchRequestOptions {
+        block,
+        ..Default::default()
+    };
+    let response = execute_batch_request(
+        &ERC20TokenMetadataBatchRequest,
+        token_addresses,
+        middleware,
+        &options,
+    )
+    .await;
+
+    let mut undecodable = vec![];
+    for (address, err) in response.failed {
+        match err {
+            AMMError::BatchDecodeError(err) => undecodable.push((address, err)),
+            err => return Err(err),
+        }
+    }
+    let tokens = response
+        .outputs
+        .into_iter()
+        .filter_map(|(_, token)| token)
+        .collect();
+
+    Ok((tokens, undecodable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethers::providers::{Http, Provider};
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn test_get_token_metadata_batch_request() {
+        dotenv::dotenv().ok();
+        let rpc_endpoint = std::env::var("NETWORK_RPC").expect("Missing NETWORK_RPC env variable");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+        let addresses = vec![
+            H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap(), // USDC
+            H160::from_str("0x9f8F72aA9304c8B593d555F12eF6589cC3A579A2").unwrap(), // MKR
+        ];
+
+        let (tokens, _) = get_token_metadata_batch_request(&addresses, middleware, None)
+            .await
+            .unwrap();
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].symbol.as_deref(), Some("USDC"));
+        assert_eq!(tokens[0].decimals, Some(6));
+        assert_eq!(tokens[1].symbol.as_deref(), Some("MKR"));
+        assert_eq!(tokens[1].decimals, Some(18));
+    }
+}
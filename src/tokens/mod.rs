@@ -0,0 +1,221 @@
+pub mod batch_request;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::read_to_string,
+    path::Path,
+    sync::Arc,
+};
+
+use ethers::{
+    abi::ParamType,
+    prelude::abigen,
+    providers::Middleware,
+    types::{BlockId, H160, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use self::batch_request::get_token_metadata_batch_request;
+use crate::{
+    errors::{AMMError, TokenCacheError},
+    uniswap_v2::{checkpoint::write_atomically, UniswapV2Pool},
+};
+
+abigen!(
+    IERC20Metadata,
+    r#"[
+        function symbol() external view returns (string)
+        function name() external view returns (string)
+        function decimals() external view returns (uint8)
+        function totalSupply() external view returns (uint256)
+    ]"#;
+);
+
+/// An ERC20 token. Fields are `None` when the token does not implement the corresponding call or
+/// returns something that cannot be decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub address: H160,
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    pub decimals: Option<u8>,
+    pub total_supply: Option<U256>,
+}
+
+impl Token {
+    /// Decodes the raw return data of the `symbol()`, `name()`, `decimals()` and `totalSupply()`
+    /// calls, empty when the call failed.
+    pub fn from_return_data(
+        address: H160,
+        symbol: &[u8],
+        name: &[u8],
+        decimals: &[u8],
+        total_supply: &[u8],
+    ) -> Token {
+        Token {
+            address,
+            symbol: decode_string(symbol),
+            name: decode_string(name),
+            decimals: decode_word(decimals)
+                .filter(|decimals| *decimals <= U256::from(u8::MAX))
+                .map(|decimals| decimals.as_u32() as u8),
+            total_supply: decode_word(total_supply),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    /// The symbol of the token, its address when it has none.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "{}", symbol),
+            None => write!(f, "{:?}", self.address),
+        }
+    }
+}
+
+/// Decodes a `string` return value, or a `bytes32` one as returned by tokens such as MKR.
+fn decode_string(data: &[u8]) -> Option<String> {
+    let string = if data.len() == 32 {
+        let end = data.iter().position(|&byte| byte == 0).unwrap_or(32);
+        String::from_utf8(data[..end].to_vec()).ok()?
+    } else {
+        ethers::abi::decode(&[ParamType::String], data)
+            .ok()?
+            .into_iter()
+            .next()?
+            .into_string()?
+    };
+
+    let string = string.trim_matches(char::from(0)).trim().to_string();
+    if string.is_empty() {
+        None
+    } else {
+        Some(string)
+    }
+}
+
+fn decode_word(data: &[u8]) -> Option<U256> {
+    if data.len() != 32 {
+        return None;
+    }
+    Some(U256::from_big_endian(data))
+}
+
+/// Token metadata by address, persisted as JSON so that it only has to be fetched once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenCache {
+    pub tokens: HashMap<H160, Token>,
+}
+
+impl TokenCache {
+    pub fn new() -> TokenCache {
+        TokenCache::default()
+    }
+
+    pub fn read_from_path(path: &str) -> Result<TokenCache, TokenCacheError> {
+        Ok(serde_json::from_str(read_to_string(path)?.as_str())?)
+    }
+
+    /// Reads the cache at `path`, starting from an empty cache when there is none yet.
+    pub fn read_from_path_or_default(path: &str) -> Result<TokenCache, TokenCacheError> {
+        match TokenCache::read_from_path(path) {
+            Err(TokenCacheError::IOError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(TokenCache::default())
+            }
+            result => result,
+        }
+    }
+
+    pub fn save_to_path(&self, path: &str) -> Result<(), TokenCacheError> {
+        write_atomically(
+            Path::new(path),
+            serde_json::to_string_pretty(&self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, address: &H160) -> Option<&Token> {
+        self.tokens.get(address)
+    }
+
+    pub fn insert(&mut self, token: Token) {
+        self.tokens.insert(token.address, token);
+    }
+
+    /// The symbol of the token at `address`, its address when it is unknown or has no symbol.
+    pub fn symbol(&self, address: H160) -> String {
+        match self.get(&address) {
+            Some(token) => token.to_string(),
+            None => format!("{:?}", address),
+        }
+    }
+
+    /// The pair of symbols of the pool tokens, e.g. `USDC/WETH`.
+    pub fn pool_symbols(&self, pool: &UniswapV2Pool) -> String {
+        format!(
+            "{}/{}",
+            self.symbol(pool.token_a),
+            self.symbol(pool.token_b)
+        )
+    }
+
+    /// Fetches the metadata of the tokens not in the cache yet, returning the number of tokens
+    /// added. Addresses without code and tokens whose metadata cannot be decoded are not cached.
+    pub async fn fetch_missing<M: Middleware>(
+        &mut self,
+        addresses: impl IntoIterator<Item = H160>,
+        middleware: Arc<M>,
+        block: Option<BlockId>,
+    ) -> Result<usize, AMMError<M>> {
+        let missing: Vec<H160> = addresses
+            .into_iter()
+            .filter(|address| !self.tokens.contains_key(address))
+            .collect::<HashSet<H160>>()
+            .into_iter()
+            .collect();
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let (tokens, _) = get_token_metadata_batch_request(&missing, middleware, block).await?;
+        let added = tokens.len();
+        for token in tokens {
+            self.insert(token);
+        }
+        Ok(added)
+    }
+
+    /// Fetches the metadata of the tokens of `pools` not in the cache yet.
+    pub async fn fetch_missing_for_pools<M: Middleware>(
+        &mut self,
+        pools: &[UniswapV2Pool],
+        middleware: Arc<M>,
+        block: Option<BlockId>,
+    ) -> Result<usize, AMMError<M>> {
+        let addresses = pools
+            .iter()
+            .flat_map(|pool| [pool.token_a, pool.token_b])
+            .collect::<Vec<H160>>();
+        self.fetch_missing(addresses, middleware, block).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_string_and_bytes32_symbols() {
+        let usdc = ethers::abi::encode(&[ethers::abi::Token::String("USDC".to_string())]);
+        assert_eq!(decode_string(&usdc), Some("USDC".to_string()));
+
+        let mut mkr = [0u8; 32];
+        mkr[..3].copy_from_slice(b"MKR");
+        assert_eq!(decode_string(&mkr), Some("MKR".to_string()));
+
+        assert_eq!(decode_string(&[]), None);
+        assert_eq!(decode_string(&[0u8; 32]), None);
+    }
+}
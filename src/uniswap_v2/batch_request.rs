@@ -4,17 +4,27 @@ use std::{
     vec,
 };
 
+use async_trait::async_trait;
 use ethers::{
-    abi::{ParamType, Token},
+    abi::{Abi, ParamType, Token},
     providers::Middleware,
-    types::{Bytes, H160, U256},
+    types::{BlockId, Bytes, H160, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch_request::{
+        execute_batch_request, is_transport_error, resolve_block_number, BatchRequest,
+        BatchRequestOptions, CallTelemetry,
+    },
+    errors::{AMMError, BatchDecodeError},
+    multicall::{aggregate3, MulticallCall, MulticallResult},
 };
-use futures::future;
-use indicatif::ProgressBar;
-
-use crate::errors::AMMError;
 
-use super::UniswapV2Pool;
+use super::{
+    checkpoint::raw_bytes, factory::IUNISWAPV2FACTORY_ABI, UniswapV2Pool, IERC20_ABI,
+    IUNISWAPV2PAIR_ABI,
+};
 
 use ethers::prelude::abigen;
 
@@ -25,18 +35,70 @@ abigen!(
     "src/contracts/GetUniswapV2PairsBatchRequest.json";
     GetWethValueInPoolBatchRequest,
     "src/contracts/GetWethValueInPoolBatchRequest.json";
+    IGetUniswapV2ReservesBatchRequest,
+    "src/contracts/GetUniswapV2ReservesBatchRequest.json";
 );
 
+/// Number of pools per reserves request, `getReserves` costs a single storage read so chunks can
+/// be much larger than for the pool data request.
+const RESERVES_BATCH_STEP: usize = 500;
+
+/// Why `GetUniswapV2PoolDataBatchRequest` rejected a pool, decoded from its per-pool status code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    MissingPoolCode,
+    /// A `token0`, `token1` or `getReserves` call to the pool reverted, only reported by the
+    /// Multicall3 backend as it reverts the whole deployless batch.
+    PoolCallFailed,
+    MissingTokenCode(#[serde(with = "raw_bytes")] H160),
+    DecimalsCallFailed(#[serde(with = "raw_bytes")] H160),
+    InvalidDecimals(#[serde(with = "raw_bytes")] H160),
+    UnknownStatus(u8),
+    /// The entry returned for the pool could not be decoded, holds the offending field.
+    InvalidField(String),
+    /// The batch call still failed with the pool on its own, e.g. reverted or ran out of gas.
+    CallReverted,
+}
+
+impl RejectReason {
+    fn from_status(status: u8, token_a: H160, token_b: H160) -> Option<RejectReason> {
+        match status {
+            0 => None,
+            1 => Some(RejectReason::MissingPoolCode),
+            2 => Some(RejectReason::MissingTokenCode(token_a)),
+            3 => Some(RejectReason::MissingTokenCode(token_b)),
+            4 => Some(RejectReason::DecimalsCallFailed(token_a)),
+            5 => Some(RejectReason::DecimalsCallFailed(token_b)),
+            6 => Some(RejectReason::InvalidDecimals(token_a)),
+            7 => Some(RejectReason::InvalidDecimals(token_b)),
+            status => Some(RejectReason::UnknownStatus(status)),
+        }
+    }
+
+    /// Mirrors `getTokenDecimals` of the batch contract on the result of a `decimals()` call.
+    fn from_decimals_result(token: H160, result: &MulticallResult) -> Result<u8, RejectReason> {
+        let decimals = result
+            .decode_word()
+            .ok_or(RejectReason::DecimalsCallFailed(token))?;
+        if decimals.is_zero() || decimals > U256::from(u8::MAX) {
+            return Err(RejectReason::InvalidDecimals(token));
+        }
+        Ok(decimals.as_u32() as u8)
+    }
+}
+
 pub async fn get_uniswap_v2_pool_data_batch_request_single<M: Middleware>(
     pool_address: H160,
     fee: u32,
     middleware: Arc<M>,
 ) -> Result<UniswapV2Pool, AMMError<M>> {
-    let pools =
-        get_uniswap_v2_pool_data_batch_request(&vec![pool_address], fee, middleware).await?;
+    let (pools, rejected) =
+        get_uniswap_v2_pool_data_batch_request(&[pool_address], fee, middleware, None).await?;
 
     if let Some(pool) = pools.get(0) {
         Ok(pool.clone())
+    } else if let Some((_, reason)) = rejected.first() {
+        Err(AMMError::<M>::PoolRejected(pool_address, reason.clone()))
     } else {
         Err(AMMError::<M>::BatchRequestError(pool_address))
     }
@@ -45,241 +107,698 @@ pub async fn get_uniswap_v2_pool_data_batch_request_single<M: Middleware>(
 struct TokenHelper;
 
 impl TokenHelper {
-    fn token_to_address(token: &Token, address: H160) -> H160 {
-        token.to_owned().into_address().expect(&format!(
-            "Expected addresses for token and address {:?}",
-            address
-        ))
+    fn field<'a>(
+        tokens: &'a [Token],
+        index: usize,
+        address: H160,
+        field: &'static str,
+    ) -> Result<&'a Token, BatchDecodeError> {
+        tokens
+            .get(index)
+            .ok_or(BatchDecodeError::InvalidField { address, field })
     }
 
-    fn token_to_u<U: TryFrom<u128>>(token: &Token, address: H160) -> U {
-        let value = token
+    fn token_to_address(
+        token: &Token,
+        address: H160,
+        field: &'static str,
+    ) -> Result<H160, BatchDecodeError> {
+        token
             .to_owned()
-            .into_uint()
-            .expect(&format!(
-                "Expected integer for token decimals and address {:?}",
-                address
-            ))
-            .as_u128();
-
-        U::try_from(value).unwrap_or_else(&|_| {
-            panic!(
-                "Failed to convert value {} to the desired unsigned integer type for address {:?}",
-                value, address
-            )
-        })
+            .into_address()
+            .ok_or(BatchDecodeError::InvalidField { address, field })
     }
 
-    fn token_to_uniswap_pool(token: &Token, address: H160, fee: u32) -> UniswapV2Pool {
-        let tup = &token.clone().into_tuple().unwrap();
-        UniswapV2Pool {
-            token_a: TokenHelper::token_to_address(&tup[0], address),
-            token_a_decimals: TokenHelper::token_to_u::<u8>(&tup[1], address),
-            token_b: TokenHelper::token_to_address(&tup[2], address),
-            token_b_decimals: TokenHelper::token_to_u::<u8>(&tup[3], address),
-            reserve_0: TokenHelper::token_to_u::<u128>(&tup[4], address),
-            reserve_1: TokenHelper::token_to_u::<u128>(&tup[5], address),
+    fn token_to_u<U: TryFrom<u128>>(
+        token: &Token,
+        address: H160,
+        field: &'static str,
+    ) -> Result<U, BatchDecodeError> {
+        let error = BatchDecodeError::InvalidField { address, field };
+        let value = token.to_owned().into_uint().ok_or(error.clone())?;
+        let value = u128::try_from(value).map_err(|_| error.clone())?;
+        U::try_from(value).map_err(|_| error)
+    }
+
+    fn token_to_uniswap_pool(
+        token: &Token,
+        address: H160,
+        fee: u32,
+    ) -> Result<Result<UniswapV2Pool, RejectReason>, BatchDecodeError> {
+        let tup = token
+            .clone()
+            .into_tuple()
+            .ok_or(BatchDecodeError::InvalidField {
+                address,
+                field: "pool data",
+            })?;
+        let field = |index, field| TokenHelper::field(&tup, index, address, field);
+
+        let token_a = TokenHelper::token_to_address(field(0, "token a")?, address, "token a")?;
+        let token_b = TokenHelper::token_to_address(field(2, "token b")?, address, "token b")?;
+        let status = TokenHelper::token_to_u::<u8>(field(7, "status")?, address, "status")?;
+        if let Some(reason) = RejectReason::from_status(status, token_a, token_b) {
+            return Ok(Err(reason));
+        }
+        Ok(Ok(UniswapV2Pool {
+            token_a,
+            token_a_decimals: TokenHelper::token_to_u::<u8>(
+                field(1, "token a decimals")?,
+                address,
+                "token a decimals",
+            )?,
+            token_b,
+            token_b_decimals: TokenHelper::token_to_u::<u8>(
+                field(3, "token b decimals")?,
+                address,
+                "token b decimals",
+            )?,
+            reserve_0: TokenHelper::token_to_u::<u128>(
+                field(4, "reserve 0")?,
+                address,
+                "reserve 0",
+            )?,
+            reserve_1: TokenHelper::token_to_u::<u128>(
+                field(5, "reserve 1")?,
+                address,
+                "reserve 1",
+            )?,
+            block_timestamp_last: TokenHelper::token_to_u::<u32>(
+                field(6, "block timestamp last")?,
+                address,
+                "block timestamp last",
+            )?,
+            block_number: 0,
             address,
             fee,
-        }
+            creation: None,
+            factory: H160::zero(),
+        }))
     }
 }
 
-pub async fn get_uniswap_v2_pool_data_batch_request<M: Middleware>(
-    pair_addresses: &Vec<H160>,
-    fee: u32,
-    middleware: Arc<M>,
-) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
-    let target_addresses: Vec<Token> = pair_addresses
-        .iter()
-        .map(|&address| Token::Address(address))
-        .collect();
-    let constructor_args = Token::Tuple(vec![Token::Array(target_addresses)]);
-    let deployer = IGetUniswapV2PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
-    let return_data: Bytes = deployer.call_raw().await?;
-    let return_data_tokens = ethers::abi::decode(
-        &[ParamType::Array(Box::new(ParamType::Tuple(vec![
+pub struct UniswapV2PoolDataBatchRequest {
+    pub fee: u32,
+}
+
+#[async_trait]
+impl BatchRequest for UniswapV2PoolDataBatchRequest {
+    type Input = H160;
+    type Output = Result<UniswapV2Pool, RejectReason>;
+
+    fn name(&self) -> &'static str {
+        "uniswap v2 pool data"
+    }
+
+    fn abi(&self) -> &Abi {
+        &IGETUNISWAPV2POOLDATABATCHREQUEST_ABI
+    }
+
+    fn bytecode(&self) -> &Bytes {
+        &IGETUNISWAPV2POOLDATABATCHREQUEST_BYTECODE
+    }
+
+    fn constructor_args(&self, inputs: &[H160]) -> Vec<Token> {
+        vec![Token::Array(
+            inputs
+                .iter()
+                .map(|&address| Token::Address(address))
+                .collect(),
+        )]
+    }
+
+    fn return_type(&self) -> ParamType {
+        ParamType::Tuple(vec![
             ParamType::Address,   // token a
             ParamType::Uint(8),   // token a decimals
             ParamType::Address,   // token b
             ParamType::Uint(8),   // token b decimals
             ParamType::Uint(112), // reserve 0
             ParamType::Uint(112), // reserve 1
-        ])))],
-        &return_data,
-    )?;
+            ParamType::Uint(32),  // block timestamp last
+            ParamType::Uint(8),   // status
+        ])
+    }
 
-    let err = AMMError::<M>::BatchRequestError;
+    fn decode(
+        &self,
+        input: &H160,
+        token: Token,
+    ) -> Result<Result<UniswapV2Pool, RejectReason>, BatchDecodeError> {
+        TokenHelper::token_to_uniswap_pool(&token, *input, self.fee)
+    }
 
-    let mut pools = vec![];
-    return_data_tokens
-        .into_iter()
-        .next()
-        .ok_or(err(H160::zero()))?
-        .into_array()
-        .ok_or(err(H160::zero()))?
-        .into_iter()
-        .enumerate()
-        .for_each(|(idx, token)| {
-            pools.push(TokenHelper::token_to_uniswap_pool(
-                &token,
-                pair_addresses[idx],
-                fee,
-            ));
-        });
-
-    Ok(pools)
+    async fn multicall<M: Middleware>(
+        &self,
+        inputs: &[H160],
+        middleware: Arc<M>,
+        options: &BatchRequestOptions,
+        telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Option<Result<Vec<Self::Output>, AMMError<M>>> {
+        Some(
+            self.pool_data_multicall(inputs, middleware, options, telemetry)
+                .await,
+        )
+    }
 }
 
-pub async fn get_uniswap_v2_pairs_batch_request<M: Middleware>(
-    factory_address: H160,
-    from: U256,
-    to: U256,
-    middleware: Arc<M>,
-) -> Result<Vec<H160>, AMMError<M>> {
-    let mut pairs = vec![];
-    let constructor_args = Token::Tuple(vec![
-        Token::Uint(from),
-        Token::Uint(to),
-        Token::Address(factory_address),
-    ]);
-    let deployer = IGetUniswapV2PairsBatchRequest::deploy(middleware.clone(), constructor_args)?;
-    let return_data: Bytes = deployer.call_raw().await?;
-    let return_data_tokens = ethers::abi::decode(
-        &[ParamType::Array(Box::new(ParamType::Address))],
-        &return_data,
-    )?;
-
-    return_data_tokens
-        .into_iter()
-        .next()
-        .ok_or(AMMError::<M>::BatchRequestError(factory_address))?
-        .into_array()
-        .ok_or(AMMError::<M>::BatchRequestError(factory_address))?
-        .into_iter()
-        .for_each({
-            |token| match token.into_address() {
-                Some(addr) => {
-                    if !addr.is_zero() {
-                        pairs.push(addr);
-                    }
-                }
-                None => (),
+impl UniswapV2PoolDataBatchRequest {
+    /// Fetches the pool data in two rounds, `token0`, `token1` and `getReserves` of the pools
+    /// first and then `decimals()` of their tokens.
+    async fn pool_data_multicall<M: Middleware>(
+        &self,
+        inputs: &[H160],
+        middleware: Arc<M>,
+        options: &BatchRequestOptions,
+        telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Result<Vec<Result<UniswapV2Pool, RejectReason>>, AMMError<M>> {
+        let pair_functions = [
+            IUNISWAPV2PAIR_ABI.function("token0")?,
+            IUNISWAPV2PAIR_ABI.function("token1")?,
+            IUNISWAPV2PAIR_ABI.function("getReserves")?,
+        ];
+        let mut calls = Vec::with_capacity(inputs.len() * pair_functions.len());
+        for &address in inputs {
+            for function in pair_functions {
+                calls.push(MulticallCall::new(address, function, &[])?);
             }
-        });
+        }
+        let pool_states: Vec<_> = aggregate3(
+            calls,
+            middleware.clone(),
+            options.block,
+            options.state.as_ref(),
+            telemetry,
+        )
+        .await?
+        .chunks(pair_functions.len())
+        .map(pool_state_from_multicall_results)
+        .collect();
 
-    Ok(pairs)
+        // Only ask for the decimals of the tokens of pools that answered
+        let decimals = IERC20_ABI.function("decimals")?;
+        let mut calls = vec![];
+        for (token_a, token_b, _) in pool_states.iter().flatten() {
+            calls.push(MulticallCall::new(*token_a, decimals, &[])?);
+            calls.push(MulticallCall::new(*token_b, decimals, &[])?);
+        }
+        let decimals_results = if calls.is_empty() {
+            vec![]
+        } else {
+            aggregate3(
+                calls,
+                middleware,
+                options.block,
+                options.state.as_ref(),
+                telemetry,
+            )
+            .await?
+        };
+        let mut decimals_results = decimals_results.chunks(2);
+
+        Ok(inputs
+            .iter()
+            .zip(pool_states)
+            .map(|(&address, pool_state)| {
+                let (token_a, token_b, (reserve_0, reserve_1, block_timestamp_last)) = pool_state?;
+                let Some([token_a_result, token_b_result]) = decimals_results.next() else {
+                    return Err(RejectReason::DecimalsCallFailed(token_a));
+                };
+
+                // Same precedence as the batch contract, code checks before decimals checks
+                if token_a_result.is_empty_success() {
+                    return Err(RejectReason::MissingTokenCode(token_a));
+                }
+                if token_b_result.is_empty_success() {
+                    return Err(RejectReason::MissingTokenCode(token_b));
+                }
+                Ok(UniswapV2Pool {
+                    address,
+                    token_a,
+                    token_a_decimals: RejectReason::from_decimals_result(token_a, token_a_result)?,
+                    token_b,
+                    token_b_decimals: RejectReason::from_decimals_result(token_b, token_b_result)?,
+                    reserve_0,
+                    reserve_1,
+                    block_timestamp_last,
+                    block_number: 0,
+                    fee: self.fee,
+                    creation: None,
+                    factory: H160::zero(),
+                })
+            })
+            .collect())
+    }
 }
 
-async fn get_weth_value_in_pool_batch_request<M: Middleware>(
-    addresses: Vec<H160>,
-    weth_address: H160,
-    factory_address: H160,
-    middleware: Arc<M>,
-    progress_bar: Option<Arc<Mutex<ProgressBar>>>,
-) -> Result<HashMap<H160, U256>, AMMError<M>> {
-    let addresses_token: Vec<Token> = addresses
+/// Tokens and `getReserves` of a pool.
+type PoolState = (H160, H160, (u128, u128, u32));
+
+/// Decodes the `token0`, `token1` and `getReserves` results of a pool.
+fn pool_state_from_multicall_results(
+    results: &[MulticallResult],
+) -> Result<PoolState, RejectReason> {
+    if results.iter().any(MulticallResult::is_empty_success) {
+        return Err(RejectReason::MissingPoolCode);
+    }
+    let decode = |result: &MulticallResult, name: &str| {
+        result.decode(IUNISWAPV2PAIR_ABI.function(name).ok()?)
+    };
+    let token = |result: &MulticallResult, name: &str| {
+        decode(result, name)?.into_iter().next()?.into_address()
+    };
+    let reserves = |result: &MulticallResult| {
+        let mut reserves = decode(result, "getReserves")?.into_iter();
+        let reserve_0 = reserves.next()?.into_uint()?.as_u128();
+        let reserve_1 = reserves.next()?.into_uint()?.as_u128();
+        let block_timestamp_last = reserves.next()?.into_uint()?.as_u32();
+        Some((reserve_0, reserve_1, block_timestamp_last))
+    };
+
+    match results {
+        [token_a, token_b, reserves_result] => match (
+            token(token_a, "token0"),
+            token(token_b, "token1"),
+            reserves(reserves_result),
+        ) {
+            (Some(token_a), Some(token_b), Some(reserves)) => Ok((token_a, token_b, reserves)),
+            _ => Err(RejectReason::PoolCallFailed),
+        },
+        _ => Err(RejectReason::PoolCallFailed),
+    }
+}
+
+/// Reads `allPairs` of the factory, the inputs are consecutive `allPairs` indices.
+pub struct UniswapV2PairsBatchRequest {
+    pub factory: H160,
+}
+
+#[async_trait]
+impl BatchRequest for UniswapV2PairsBatchRequest {
+    type Input = U256;
+    type Output = H160;
+
+    fn name(&self) -> &'static str {
+        "uniswap v2 pairs"
+    }
+
+    fn abi(&self) -> &Abi {
+        &IGETUNISWAPV2PAIRSBATCHREQUEST_ABI
+    }
+
+    fn bytecode(&self) -> &Bytes {
+        &IGETUNISWAPV2PAIRSBATCHREQUEST_BYTECODE
+    }
+
+    fn constructor_args(&self, inputs: &[U256]) -> Vec<Token> {
+        let from = inputs.first().copied().unwrap_or_default();
+        vec![
+            Token::Uint(from),
+            Token::Uint(from + inputs.len()),
+            Token::Address(self.factory),
+        ]
+    }
+
+    fn return_type(&self) -> ParamType {
+        ParamType::Address
+    }
+
+    fn decode(&self, _input: &U256, token: Token) -> Result<H160, BatchDecodeError> {
+        TokenHelper::token_to_address(&token, self.factory, "pair")
+    }
+
+    async fn multicall<M: Middleware>(
+        &self,
+        inputs: &[U256],
+        middleware: Arc<M>,
+        options: &BatchRequestOptions,
+        telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Option<Result<Vec<H160>, AMMError<M>>> {
+        Some(
+            self.pairs_multicall(inputs, middleware, options, telemetry)
+                .await,
+        )
+    }
+}
+
+impl UniswapV2PairsBatchRequest {
+    async fn pairs_multicall<M: Middleware>(
+        &self,
+        inputs: &[U256],
+        middleware: Arc<M>,
+        options: &BatchRequestOptions,
+        telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Result<Vec<H160>, AMMError<M>> {
+        let all_pairs = IUNISWAPV2FACTORY_ABI.function("allPairs")?;
+        let calls = inputs
+            .iter()
+            .map(|&index| MulticallCall::new(self.factory, all_pairs, &[Token::Uint(index)]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Like the batch contract, indices that fail to resolve yield the zero address
+        Ok(aggregate3(
+            calls,
+            middleware,
+            options.block,
+            options.state.as_ref(),
+            telemetry,
+        )
+        .await?
         .iter()
-        .map(|&address| Token::Address(address))
-        .collect();
-    let constructor_args = Token::Tuple(vec![
-        Token::Array(addresses_token),
-        Token::Address(factory_address),
-        Token::Address(weth_address),
-    ]);
-    let deployer = GetWethValueInPoolBatchRequest::deploy(middleware.clone(), constructor_args)?;
-    let return_data: Bytes = deployer
-        .call_raw()
-        .await
-        .map_err(|_err| AMMError::OutOfGasError(addresses.clone()))?;
-    let return_data_tokens = ethers::abi::decode(
-        &[ParamType::Array(Box::new(ParamType::Uint(256)))],
-        &return_data,
-    )?;
-
-    let mut weth_values_in_pools: HashMap<H160, U256> = HashMap::new();
-
-    for token_array in return_data_tokens {
-        if let Some(arr) = token_array.into_array() {
-            for (idx, token) in arr.into_iter().enumerate() {
-                if let Some(weth_value_in_pool) = token.into_uint() {
-                    weth_values_in_pools.insert(addresses[idx], weth_value_in_pool);
-                }
-            }
+        .map(|result| {
+            result
+                .decode(all_pairs)
+                .and_then(|tokens| tokens.into_iter().next()?.into_address())
+                .unwrap_or_default()
+        })
+        .collect())
+    }
+}
+
+pub struct WethValueInPoolBatchRequest {
+    pub weth: H160,
+    pub factory: H160,
+}
+
+impl BatchRequest for WethValueInPoolBatchRequest {
+    type Input = H160;
+    type Output = U256;
+
+    fn name(&self) -> &'static str {
+        "weth value in pool"
+    }
+
+    fn abi(&self) -> &Abi {
+        &GETWETHVALUEINPOOLBATCHREQUEST_ABI
+    }
+
+    fn bytecode(&self) -> &Bytes {
+        &GETWETHVALUEINPOOLBATCHREQUEST_BYTECODE
+    }
+
+    fn constructor_args(&self, inputs: &[H160]) -> Vec<Token> {
+        vec![
+            Token::Array(
+                inputs
+                    .iter()
+                    .map(|&address| Token::Address(address))
+                    .collect(),
+            ),
+            Token::Address(self.factory),
+            Token::Address(self.weth),
+        ]
+    }
+
+    fn return_type(&self) -> ParamType {
+        ParamType::Uint(256)
+    }
+
+    fn decode(&self, input: &H160, token: Token) -> Result<U256, BatchDecodeError> {
+        token.into_uint().ok_or(BatchDecodeError::InvalidField {
+            address: *input,
+            field: "weth value",
+        })
+    }
+}
+
+/// Reads `getReserves` of known pairs, leaving out the token data of the pool data request.
+pub struct UniswapV2ReservesBatchRequest;
+
+#[async_trait]
+impl BatchRequest for UniswapV2ReservesBatchRequest {
+    type Input = H160;
+    /// Reserves and `blockTimestampLast`, `None` when the pool has no code or the call reverted.
+    type Output = Option<(u128, u128, u32)>;
+
+    fn name(&self) -> &'static str {
+        "uniswap v2 reserves"
+    }
+
+    fn abi(&self) -> &Abi {
+        &IGETUNISWAPV2RESERVESBATCHREQUEST_ABI
+    }
+
+    fn bytecode(&self) -> &Bytes {
+        &IGETUNISWAPV2RESERVESBATCHREQUEST_BYTECODE
+    }
+
+    fn constructor_args(&self, inputs: &[H160]) -> Vec<Token> {
+        vec![Token::Array(
+            inputs
+                .iter()
+                .map(|&address| Token::Address(address))
+                .collect(),
+        )]
+    }
+
+    fn return_type(&self) -> ParamType {
+        ParamType::Tuple(vec![
+            ParamType::Uint(112), // reserve 0
+            ParamType::Uint(112), // reserve 1
+            ParamType::Uint(32),  // block timestamp last
+            ParamType::Bool,      // success
+        ])
+    }
+
+    fn decode(
+        &self,
+        input: &H160,
+        token: Token,
+    ) -> Result<Option<(u128, u128, u32)>, BatchDecodeError> {
+        let address = *input;
+        let tup = token.into_tuple().ok_or(BatchDecodeError::InvalidField {
+            address,
+            field: "reserves",
+        })?;
+        let field = |index, field| TokenHelper::field(&tup, index, address, field);
+
+        let success =
+            field(3, "success")?
+                .clone()
+                .into_bool()
+                .ok_or(BatchDecodeError::InvalidField {
+                    address,
+                    field: "success",
+                })?;
+        if !success {
+            return Ok(None);
         }
+        Ok(Some((
+            TokenHelper::token_to_u::<u128>(field(0, "reserve 0")?, address, "reserve 0")?,
+            TokenHelper::token_to_u::<u128>(field(1, "reserve 1")?, address, "reserve 1")?,
+            TokenHelper::token_to_u::<u32>(
+                field(2, "block timestamp last")?,
+                address,
+                "block timestamp last",
+            )?,
+        )))
     }
 
-    if let Some(progress_bar) = progress_bar {
-        progress_bar.lock().unwrap().inc((addresses.len()) as u64);
+    async fn multicall<M: Middleware>(
+        &self,
+        inputs: &[H160],
+        middleware: Arc<M>,
+        options: &BatchRequestOptions,
+        telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+    ) -> Option<Result<Vec<Self::Output>, AMMError<M>>> {
+        Some(reserves_multicall(inputs, middleware, options, telemetry).await)
     }
-    Ok(weth_values_in_pools)
 }
 
-pub async fn get_weth_value_in_pools<M: Middleware>(
-    addresses: Vec<H160>,
-    weth_address: H160,
-    factory_address: H160,
+async fn reserves_multicall<M: Middleware>(
+    inputs: &[H160],
     middleware: Arc<M>,
-    step: Option<usize>,
-) -> Result<HashMap<H160, U256>, AMMError<M>> {
-    let step = match step {
-        Some(step) => step,
-        None => 100,
+    options: &BatchRequestOptions,
+    telemetry: Option<&Mutex<Vec<CallTelemetry>>>,
+) -> Result<Vec<Option<(u128, u128, u32)>>, AMMError<M>> {
+    let get_reserves = IUNISWAPV2PAIR_ABI.function("getReserves")?;
+    let calls = inputs
+        .iter()
+        .map(|&address| MulticallCall::new(address, get_reserves, &[]))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(aggregate3(
+        calls,
+        middleware,
+        options.block,
+        options.state.as_ref(),
+        telemetry,
+    )
+    .await?
+    .iter()
+    .map(|result| {
+        let mut reserves = result.decode(get_reserves)?.into_iter();
+        let reserve_0 = reserves.next()?.into_uint()?.as_u128();
+        let reserve_1 = reserves.next()?.into_uint()?.as_u128();
+        let block_timestamp_last = reserves.next()?.into_uint()?.as_u32();
+        Some((reserve_0, reserve_1, block_timestamp_last))
+    })
+    .collect())
+}
+
+/// Refreshes the reserves of known pools in place at `block`, the current block when unset,
+/// without re-fetching their tokens. Returns the pools whose reserves could not be fetched, which
+/// are left untouched.
+pub async fn refresh_reserves<M: Middleware>(
+    pools: &mut [UniswapV2Pool],
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+) -> Result<Vec<H160>, AMMError<M>> {
+    let block_number = resolve_block_number(middleware.clone(), block).await?;
+    let options = BatchRequestOptions {
+        step: Some(RESERVES_BATCH_STEP),
+        block: Some(block_number.into()),
+        ..Default::default()
     };
-    println!(
-        "Getting weth equivalent value of {} uniswap pools",
-        addresses.len()
-    );
-    let pb = ProgressBar::new(addresses.len() as u64);
-    let shared_pb = Arc::new(Mutex::new(pb));
-    let mut futures: Vec<_> = vec![];
-    for i in (0..addresses.len()).step_by(step) {
-        futures.push(get_weth_value_in_pool_batch_request(
-            addresses[i..(i + step).min(addresses.len())].to_vec(),
-            weth_address,
-            factory_address,
-            middleware.clone(),
-            Some(shared_pb.clone()),
-        ));
-    }
-    let results: Vec<std::result::Result<HashMap<H160, ethers::types::U256>, AMMError<M>>> =
-        future::join_all(futures).await;
-    let mut weth_values_in_pools: HashMap<H160, U256> = HashMap::new();
-    let mut failed_addresses: Vec<H160> = vec![];
-    for result in results {
-        match result {
-            Ok(mut weth_values_in_pools_batch) => {
-                weth_values_in_pools.extend(weth_values_in_pools_batch.drain())
-            }
-            Err(AMMError::OutOfGasError(failed_batch)) => {
-                failed_addresses.extend(failed_batch);
+    let addresses: Vec<H160> = pools.iter().map(|pool| pool.address).collect();
+    let response = execute_batch_request(
+        &UniswapV2ReservesBatchRequest,
+        &addresses,
+        middleware,
+        &options,
+    )
+    .await;
+
+    // As for the pool data, pools whose call failed on its own are reported as failed, only
+    // failing to reach the node fails the request
+    let mut failed = vec![];
+    for (address, err) in response.failed {
+        if is_transport_error(&err) {
+            return Err(err);
+        }
+        failed.push(address);
+    }
+    let mut reserves: HashMap<H160, (u128, u128, u32)> = HashMap::new();
+    for (address, output) in response.outputs {
+        match output {
+            Some(output) => {
+                reserves.insert(address, output);
             }
-            Err(err) => return Err(err),
+            None => failed.push(address),
         }
     }
 
-    let mut futures: Vec<_> = vec![];
-    for address in failed_addresses {
-        futures.push(get_weth_value_in_pool_batch_request(
-            vec![address],
-            weth_address,
-            factory_address,
-            middleware.clone(),
-            None,
-        ));
+    for pool in pools.iter_mut() {
+        if let Some(&(reserve_0, reserve_1, block_timestamp_last)) = reserves.get(&pool.address) {
+            pool.reserve_0 = reserve_0;
+            pool.reserve_1 = reserve_1;
+            pool.block_timestamp_last = block_timestamp_last;
+            pool.block_number = block_number;
+        }
     }
-    let results: Vec<std::result::Result<HashMap<H160, ethers::types::U256>, AMMError<M>>> =
-        future::join_all(futures).await;
-    for result in results {
-        match result {
-            Ok(mut weth_values_in_pools_batch) => {
-                weth_values_in_pools.extend(weth_values_in_pools_batch.drain())
-            }
-            Err(err) => return Err(err),
+
+    Ok(failed)
+}
+
+/// Fetches the pool data of every pair at `block`, splitting the result into valid pools and the
+/// pairs the batch contract rejected. Pools record the block their reserves were fetched at, the
+/// current block when `block` is unset.
+pub async fn get_uniswap_v2_pool_data_batch_request<M: Middleware>(
+    pair_addresses: &[H160],
+    fee: u32,
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>), AMMError<M>> {
+    let block_number = resolve_block_number(middleware.clone(), block).await?;
+    let options = BatchRequestOptions {
+        block: Some(block_number.into()),
+        ..Default::default()
+    };
+    let response = execute_batch_request(
+        &UniswapV2PoolDataBatchRequest { fee },
+        pair_addresses,
+        middleware,
+        &options,
+    )
+    .await;
+
+    let mut pools = vec![];
+    let mut rejected = vec![];
+    for (address, output) in response.outputs {
+        match output {
+            Ok(pool) => pools.push(UniswapV2Pool {
+                block_number,
+                ..pool
+            }),
+            Err(reason) => rejected.push((address, reason)),
         }
     }
+    // Pools whose entry could not be decoded or whose call failed on its own are reported along
+    // with the rejected ones, only failing to reach the node fails the request
+    for (address, err) in response.failed {
+        let reason = match err {
+            AMMError::BatchDecodeError(BatchDecodeError::InvalidField { field, .. }) => {
+                RejectReason::InvalidField(field.to_string())
+            }
+            err if is_transport_error(&err) => return Err(err),
+            _ => RejectReason::CallReverted,
+        };
+        rejected.push((address, reason));
+    }
+
+    Ok((pools, rejected))
+}
+
+pub async fn get_uniswap_v2_pairs_batch_request<M: Middleware>(
+    factory_address: H160,
+    from: U256,
+    to: U256,
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+) -> Result<Vec<H160>, AMMError<M>> {
+    let indices: Vec<U256> = (from.as_u128()..to.as_u128()).map(U256::from).collect();
+    let options = BatchRequestOptions {
+        block,
+        ..Default::default()
+    };
+    let outputs = execute_batch_request(
+        &UniswapV2PairsBatchRequest {
+            factory: factory_address,
+        },
+        &indices,
+        middleware,
+        &options,
+    )
+    .await
+    .into_outputs()?;
+
+    Ok(outputs
+        .into_iter()
+        .map(|(_, pair)| pair)
+        .filter(|pair| !pair.is_zero())
+        .collect())
+}
+
+/// Returns the weth value of every pool, and the pools whose value could not be computed, e.g.
+/// because computing it alone runs out of gas.
+pub async fn get_weth_value_in_pools<M: Middleware>(
+    addresses: Vec<H160>,
+    weth_address: H160,
+    factory_address: H160,
+    middleware: Arc<M>,
+    step: Option<usize>,
+    block: Option<BlockId>,
+) -> Result<(HashMap<H160, U256>, Vec<H160>), AMMError<M>> {
+    let request = WethValueInPoolBatchRequest {
+        weth: weth_address,
+        factory: factory_address,
+    };
+    let options = BatchRequestOptions {
+        step,
+        progress: true,
+        telemetry: true,
+        block,
+        ..Default::default()
+    };
+    let response = execute_batch_request(&request, &addresses, middleware, &options).await;
+    let weth_values_in_pools = response.outputs.into_iter().collect();
+    let failed = response
+        .failed
+        .into_iter()
+        .map(|(address, _)| address)
+        .collect();
 
-    Ok(weth_values_in_pools)
+    Ok((weth_values_in_pools, failed))
 }
 
 #[cfg(test)]
@@ -291,6 +810,31 @@ mod tests {
     use std::str::FromStr;
     use std::sync::Arc;
 
+    #[test]
+    fn test_decode_invalid_pool_data() {
+        let request = UniswapV2PoolDataBatchRequest { fee: 300 };
+        let address = H160::from_low_u64_be(1);
+        let token = Token::Tuple(vec![
+            Token::Address(H160::from_low_u64_be(2)),
+            Token::Uint(U256::from(18)),
+            Token::Address(H160::from_low_u64_be(3)),
+            Token::Uint(U256::from(300)), // decimals out of range
+            Token::Uint(U256::from(1)),
+            Token::Uint(U256::from(1)),
+            Token::Uint(U256::from(1_700_000_000)),
+            Token::Uint(U256::zero()),
+        ]);
+
+        assert_eq!(
+            request.decode(&address, token).unwrap_err(),
+            BatchDecodeError::InvalidField {
+                address,
+                field: "token b decimals"
+            }
+        );
+        assert!(request.decode(&address, Token::Bool(true)).is_err());
+    }
+
     #[tokio::test]
     async fn test_get_uniswap_v2_pool_data_batch_request_single() {
         dotenv::dotenv().ok();
@@ -337,9 +881,11 @@ mod tests {
             H160::from_str("0x811beed0119b4afce20d2583eb608c6f7af1954f").unwrap(), // SHIB<>WETH
         ];
 
-        let r = get_uniswap_v2_pool_data_batch_request(&addresses, 300, middleware.clone())
-            .await
-            .unwrap();
+        let (r, rejected) =
+            get_uniswap_v2_pool_data_batch_request(&addresses, 300, middleware.clone(), None)
+                .await
+                .unwrap();
+        assert!(rejected.is_empty());
 
         let pool1 = &r[0];
         let pool2 = &r[1];
@@ -379,6 +925,31 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_refresh_reserves() {
+        dotenv::dotenv().ok();
+        let rpc_endpoint = std::env::var("NETWORK_RPC").expect("Missing NETWORK_RPC env variable");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+        let pool_address = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
+        let mut pools = vec![
+            UniswapV2Pool {
+                address: pool_address,
+                ..Default::default()
+            },
+            UniswapV2Pool::default(), // no code at the zero address
+        ];
+
+        let failed = refresh_reserves(&mut pools, middleware.clone(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(failed, vec![H160::zero()]);
+        assert!(pools[0].reserve_0 > 0);
+        assert!(pools[0].reserve_1 > 0);
+        assert!(pools[0].block_number > 0);
+        assert_eq!(pools[1].reserve_0, 0);
+    }
+
     #[tokio::test]
     async fn test_get_weth_value_in_pool_batch_request() {
         dotenv::dotenv().ok();
@@ -387,12 +958,13 @@ mod tests {
         let pool_address = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
         let weth_address = H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
         let factory_address = H160::from_str("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f").unwrap();
-        let total = get_weth_value_in_pool_batch_request(
+        let (total, _) = get_weth_value_in_pools(
             vec![pool_address],
             weth_address,
             factory_address,
             middleware.clone(),
             None,
+            None,
         )
         .await
         .unwrap();
@@ -0,0 +1,935 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use ethers::{providers::Middleware, types::H160, utils::hex};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use self::raw_bytes::Raw;
+use super::{batch_request::RejectReason, factory::UniswapV2Factory, UniswapV2Pool};
+use crate::errors::{AMMError, CheckpointError};
+
+pub const DEFAULT_CHECKPOINT_DIR: &str = "checkpoint_data";
+
+/// Version of the checkpoint schema written by this crate. Checkpoints written before versioning
+/// have no `version` field and are version 1.
+pub const CHECKPOINT_VERSION: u32 = 3;
+
+/// A checkpoint decoded with the layout of the version it was written at, upgraded to
+/// [`CHECKPOINT_VERSION`] by [`VersionedCheckpoint::migrate`] whatever its format.
+enum VersionedCheckpoint {
+    V1(CheckpointV1),
+    /// Only binary checkpoints, version 2 and 3 JSON checkpoints share the same layout.
+    V2(v2::Checkpoint),
+    Current(Checkpoint),
+}
+
+impl VersionedCheckpoint {
+    fn migrate(self) -> Checkpoint {
+        let mut checkpoint = match self {
+            // Version 2 adds the `version` field and makes `rejected` mandatory.
+            VersionedCheckpoint::V1(v1) => {
+                Checkpoint::new(v1.timestamp, v1.block_number, v1.factory, v1.pools, vec![])
+            }
+            // Version 3 writes addresses and hashes as raw bytes in the binary formats.
+            VersionedCheckpoint::V2(v2) => v2.into(),
+            VersionedCheckpoint::Current(checkpoint) => checkpoint,
+        };
+        checkpoint.version = CHECKPOINT_VERSION;
+        checkpoint
+    }
+}
+
+/// Checkpoints written before versioning, only ever written as JSON.
+#[derive(Deserialize)]
+struct CheckpointV1 {
+    timestamp: usize,
+    block_number: u64,
+    factory: UniswapV2Factory,
+    pools: Vec<UniswapV2Pool>,
+}
+
+#[derive(Deserialize)]
+struct VersionTag {
+    version: Option<u32>,
+}
+
+/// Prefix of binary checkpoints, followed by the schema version as a little endian `u32`.
+const BINARY_MAGIC: &[u8; 8] = b"AMMCKPT\0";
+
+/// Encoding of a checkpoint file. JSON is readable, the binary formats encode the checkpoint with
+/// bincode. Checkpoints of older versions are migrated on load in every format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CheckpointFormat {
+    #[default]
+    Json,
+    Binary,
+    /// Binary compressed with zstd.
+    CompressedBinary,
+}
+
+impl CheckpointFormat {
+    /// Format matching the extension of `path`: `.bin` for binary, `.zst` for compressed binary
+    /// and JSON otherwise.
+    pub fn from_path(path: impl AsRef<Path>) -> CheckpointFormat {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("bin") => CheckpointFormat::Binary,
+            Some("zst") => CheckpointFormat::CompressedBinary,
+            _ => CheckpointFormat::Json,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CheckpointFormat::Json => "json",
+            CheckpointFormat::Binary => "bin",
+            CheckpointFormat::CompressedBinary => "zst",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub timestamp: usize,
+    pub block_number: u64,
+    pub factory: UniswapV2Factory,
+    pub pools: Vec<UniswapV2Pool>,
+    #[serde(with = "rejected")]
+    pub rejected: Vec<(H160, RejectReason)>,
+}
+
+impl Checkpoint {
+    pub fn new(
+        timestamp: usize,
+        block_number: u64,
+        factory: UniswapV2Factory,
+        pools: Vec<UniswapV2Pool>,
+        rejected: Vec<(H160, RejectReason)>,
+    ) -> Checkpoint {
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            timestamp,
+            block_number,
+            factory,
+            pools,
+            rejected,
+        }
+    }
+
+    /// Pools whose reserves were fetched more than `max_age` blocks before `current_block`.
+    pub fn stale_pools(&self, current_block: u64, max_age: u64) -> Vec<&UniswapV2Pool> {
+        self.pools
+            .iter()
+            .filter(|pool| !pool.reserves_are_fresh(current_block, max_age))
+            .collect()
+    }
+
+    /// Parses a checkpoint of any version up to [`CHECKPOINT_VERSION`], migrating it to the
+    /// current schema.
+    pub fn from_json(json: &[u8]) -> Result<Checkpoint, CheckpointError> {
+        let version = match serde_json::from_slice::<VersionTag>(json) {
+            Ok(tag) => tag.version.unwrap_or(1),
+            Err(source) if source.is_data() => {
+                return Err(CheckpointError::InvalidSchema { version: 0, source })
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let checkpoint = match version {
+            0 | 1 => serde_json::from_slice(json).map(VersionedCheckpoint::V1),
+            2 | CHECKPOINT_VERSION => {
+                serde_json::from_slice(json).map(VersionedCheckpoint::Current)
+            }
+            _ => return Err(incompatible_version(version)),
+        }
+        .map_err(|source| CheckpointError::InvalidSchema { version, source })?;
+        Ok(checkpoint.migrate())
+    }
+
+    pub fn encode(&self, format: CheckpointFormat) -> Result<Vec<u8>, CheckpointError> {
+        let bytes = match format {
+            CheckpointFormat::Json => serde_json::to_vec_pretty(self)?,
+            CheckpointFormat::Binary | CheckpointFormat::CompressedBinary => {
+                let mut bytes = BINARY_MAGIC.to_vec();
+                bytes.extend_from_slice(&self.version.to_le_bytes());
+                bincode::serialize_into(&mut bytes, self)?;
+                bytes
+            }
+        };
+        if format == CheckpointFormat::CompressedBinary {
+            return Ok(zstd::encode_all(bytes.as_slice(), 0)?);
+        }
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8], format: CheckpointFormat) -> Result<Checkpoint, CheckpointError> {
+        let decompressed;
+        let bytes = match format {
+            CheckpointFormat::Json => return Checkpoint::from_json(bytes),
+            CheckpointFormat::Binary => bytes,
+            CheckpointFormat::CompressedBinary => {
+                decompressed = zstd::decode_all(bytes)?;
+                decompressed.as_slice()
+            }
+        };
+
+        let header_length = BINARY_MAGIC.len() + 4;
+        if bytes.len() < header_length || !bytes.starts_with(BINARY_MAGIC) {
+            return Err(CheckpointError::InvalidBinaryHeader);
+        }
+        let version =
+            u32::from_le_bytes(bytes[BINARY_MAGIC.len()..header_length].try_into().unwrap());
+        let payload = &bytes[header_length..];
+        let checkpoint = match version {
+            2 => VersionedCheckpoint::V2(bincode::deserialize(payload)?),
+            CHECKPOINT_VERSION => VersionedCheckpoint::Current(bincode::deserialize(payload)?),
+            _ => return Err(incompatible_version(version)),
+        };
+        Ok(checkpoint.migrate())
+    }
+
+    /// Reads a checkpoint in the format matching the extension of `path`, see
+    /// [`CheckpointFormat::from_path`]. The content is verified against the checksum stored next
+    /// to it by [`Checkpoint::save_to_path`], checkpoints without a checksum are read unverified.
+    pub fn read_from_path(path: impl AsRef<Path>) -> Result<Checkpoint, CheckpointError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        if !matches_checksum(path, &bytes)? {
+            return Err(CheckpointError::ChecksumMismatch(path.to_path_buf()));
+        }
+        Checkpoint::decode(&bytes, CheckpointFormat::from_path(path))
+    }
+
+    /// Writes the checkpoint in the format matching the extension of `path`, along with its
+    /// checksum in `<path>.sha256`. The checkpoint replaced is kept at [`previous_path`].
+    ///
+    /// The new files are written to temporary files and renamed into place, and the replaced
+    /// checkpoint is moved away before, so an interrupted save leaves either the new checkpoint
+    /// or no checkpoint at `path` next to the previous one, never a truncated file. A replaced
+    /// checkpoint that does not match its checksum is discarded rather than kept, so that it
+    /// never takes the place of a good previous checkpoint.
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let bytes = self.encode(CheckpointFormat::from_path(path))?;
+        let checksum_file = checksum_path(path);
+        let temporary_path = append_to_file_name(path, ".tmp");
+        let temporary_checksum_path = append_to_file_name(&checksum_file, ".tmp");
+        write_synced(&temporary_path, &bytes)?;
+        write_synced(&temporary_checksum_path, sha256_hex(&bytes).as_bytes())?;
+
+        let replaced_is_valid = match fs::read(path) {
+            Ok(replaced) => matches_checksum(path, &replaced)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => false,
+            Err(err) => return Err(err.into()),
+        };
+        if replaced_is_valid {
+            let previous_path = previous_path(path);
+            let previous_checksum_path = checksum_path(&previous_path);
+            // The checksum of the previous checkpoint goes first so that it is never checked
+            // against a checkpoint it was not computed for.
+            remove_if_exists(&previous_checksum_path)?;
+            fs::rename(path, &previous_path)?;
+            if checksum_file.exists() {
+                fs::rename(&checksum_file, previous_checksum_path)?;
+            }
+        }
+        fs::rename(temporary_checksum_path, checksum_file)?;
+        fs::rename(temporary_path, path)?;
+        sync_parent_dir(path)?;
+        Ok(())
+    }
+}
+
+fn incompatible_version(version: u32) -> CheckpointError {
+    CheckpointError::IncompatibleVersion {
+        found: version,
+        supported: CHECKPOINT_VERSION,
+    }
+}
+
+/// Path the checkpoint replaced by [`Checkpoint::save_to_path`] is kept at,
+/// `checkpoint.prev.json` for `checkpoint.json`.
+pub fn previous_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(".prev");
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    append_to_file_name(path, ".sha256")
+}
+
+fn append_to_file_name(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name: OsString = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Whether `bytes`, read from `path`, match the checksum stored next to it. Checkpoints without a
+/// checksum always match.
+fn matches_checksum(path: &Path, bytes: &[u8]) -> Result<bool, CheckpointError> {
+    match fs::read_to_string(checksum_path(path)) {
+        Ok(checksum) => Ok(checksum.trim() == sha256_hex(bytes)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes `bytes` to a temporary file renamed over `path`, so that a crash leaves either the
+/// previous or the new file.
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let temporary_path = append_to_file_name(path, ".tmp");
+    write_synced(&temporary_path, bytes)?;
+    fs::rename(temporary_path, path)?;
+    sync_parent_dir(path)
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Persists the renames of the files in the directory of `path`.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened as files on other platforms.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// `#[serde(with = "raw_bytes")]` for addresses, hashes and `U256`. impl-serde writes them as hex
+/// strings whatever the format, this keeps the hex strings in JSON but writes the raw bytes in the
+/// binary formats.
+pub(crate) mod raw_bytes {
+    use ethers::types::{H160, H256, U256};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub trait RawBytes: Sized + Serialize + for<'de> Deserialize<'de> {
+        type Raw: Serialize + for<'de> Deserialize<'de>;
+
+        fn to_raw(&self) -> Self::Raw;
+
+        fn from_raw(raw: Self::Raw) -> Self;
+    }
+
+    impl RawBytes for H160 {
+        type Raw = [u8; 20];
+
+        fn to_raw(&self) -> [u8; 20] {
+            self.0
+        }
+
+        fn from_raw(raw: [u8; 20]) -> H160 {
+            H160(raw)
+        }
+    }
+
+    impl RawBytes for H256 {
+        type Raw = [u8; 32];
+
+        fn to_raw(&self) -> [u8; 32] {
+            self.0
+        }
+
+        fn from_raw(raw: [u8; 32]) -> H256 {
+            H256(raw)
+        }
+    }
+
+    impl RawBytes for U256 {
+        /// Little endian limbs.
+        type Raw = [u64; 4];
+
+        fn to_raw(&self) -> [u64; 4] {
+            self.0
+        }
+
+        fn from_raw(raw: [u64; 4]) -> U256 {
+            U256(raw)
+        }
+    }
+
+    pub fn serialize<T: RawBytes, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            value.serialize(serializer)
+        } else {
+            value.to_raw().serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T: RawBytes, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        if deserializer.is_human_readable() {
+            T::deserialize(deserializer)
+        } else {
+            T::Raw::deserialize(deserializer).map(T::from_raw)
+        }
+    }
+
+    /// Applies [`serialize`] and [`deserialize`] to values nested in collections.
+    #[derive(Serialize, Deserialize)]
+    #[serde(bound = "T: RawBytes")]
+    pub struct Raw<T>(
+        #[serde(serialize_with = "serialize", deserialize_with = "deserialize")] pub T,
+    );
+}
+
+/// `Checkpoint::rejected` with the addresses written by [`raw_bytes`].
+mod rejected {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        rejected: &[(H160, RejectReason)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            rejected
+                .iter()
+                .map(|(address, reason)| (Raw(*address), reason)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(H160, RejectReason)>, D::Error> {
+        let rejected = Vec::<(Raw<H160>, RejectReason)>::deserialize(deserializer)?;
+        Ok(rejected
+            .into_iter()
+            .map(|(Raw(address), reason)| (address, reason))
+            .collect())
+    }
+}
+
+/// Binary layout of version 2 checkpoints, the current types with addresses and hashes written as
+/// hex strings.
+mod v2 {
+    use ethers::types::{H160, H256};
+    use serde::Deserialize;
+
+    use crate::uniswap_v2::{batch_request, factory, UniswapV2Pool};
+
+    #[derive(Deserialize)]
+    pub struct Checkpoint {
+        _version: u32,
+        timestamp: usize,
+        block_number: u64,
+        factory: Factory,
+        pools: Vec<Pool>,
+        rejected: Vec<(H160, RejectReason)>,
+    }
+
+    #[derive(Deserialize)]
+    struct Factory {
+        address: H160,
+        creation_block: u64,
+        fee: u32,
+    }
+
+    #[derive(Deserialize)]
+    struct Pool {
+        address: H160,
+        token_a: H160,
+        token_a_decimals: u8,
+        token_b: H160,
+        token_b_decimals: u8,
+        reserve_0: u128,
+        reserve_1: u128,
+        block_timestamp_last: u32,
+        block_number: u64,
+        fee: u32,
+        creation: Option<PoolCreation>,
+        factory: H160,
+    }
+
+    #[derive(Deserialize)]
+    struct PoolCreation {
+        block_number: u64,
+        transaction_hash: H256,
+        log_index: u64,
+        pair_index: u64,
+    }
+
+    #[derive(Deserialize)]
+    enum RejectReason {
+        MissingPoolCode,
+        PoolCallFailed,
+        MissingTokenCode(H160),
+        DecimalsCallFailed(H160),
+        InvalidDecimals(H160),
+        UnknownStatus(u8),
+        InvalidField(String),
+        CallReverted,
+    }
+
+    impl From<Checkpoint> for super::Checkpoint {
+        fn from(checkpoint: Checkpoint) -> super::Checkpoint {
+            let factory = checkpoint.factory;
+            super::Checkpoint::new(
+                checkpoint.timestamp,
+                checkpoint.block_number,
+                factory::UniswapV2Factory::new(
+                    factory.address,
+                    factory.creation_block,
+                    factory.fee,
+                ),
+                checkpoint.pools.into_iter().map(Pool::into).collect(),
+                checkpoint
+                    .rejected
+                    .into_iter()
+                    .map(|(address, reason)| (address, reason.into()))
+                    .collect(),
+            )
+        }
+    }
+
+    impl From<Pool> for UniswapV2Pool {
+        fn from(pool: Pool) -> UniswapV2Pool {
+            UniswapV2Pool {
+                address: pool.address,
+                token_a: pool.token_a,
+                token_a_decimals: pool.token_a_decimals,
+                token_b: pool.token_b,
+                token_b_decimals: pool.token_b_decimals,
+                reserve_0: pool.reserve_0,
+                reserve_1: pool.reserve_1,
+                block_timestamp_last: pool.block_timestamp_last,
+                block_number: pool.block_number,
+                fee: pool.fee,
+                creation: pool.creation.map(|creation| factory::PoolCreation {
+                    block_number: creation.block_number,
+                    transaction_hash: creation.transaction_hash,
+                    log_index: creation.log_index,
+                    pair_index: creation.pair_index,
+                }),
+                factory: pool.factory,
+            }
+        }
+    }
+
+    impl From<RejectReason> for batch_request::RejectReason {
+        fn from(reason: RejectReason) -> batch_request::RejectReason {
+            use batch_request::RejectReason as Current;
+            match reason {
+                RejectReason::MissingPoolCode => Current::MissingPoolCode,
+                RejectReason::PoolCallFailed => Current::PoolCallFailed,
+                RejectReason::MissingTokenCode(token) => Current::MissingTokenCode(token),
+                RejectReason::DecimalsCallFailed(token) => Current::DecimalsCallFailed(token),
+                RejectReason::InvalidDecimals(token) => Current::InvalidDecimals(token),
+                RejectReason::UnknownStatus(status) => Current::UnknownStatus(status),
+                RejectReason::InvalidField(field) => Current::InvalidField(field),
+                RejectReason::CallReverted => Current::CallReverted,
+            }
+        }
+    }
+}
+
+/// Identifies the checkpoint of a factory, factories at the same address on different chains
+/// get different checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CheckpointKey {
+    pub chain_id: u64,
+    pub factory: H160,
+}
+
+impl CheckpointKey {
+    pub fn new(chain_id: u64, factory: H160) -> CheckpointKey {
+        CheckpointKey { chain_id, factory }
+    }
+
+    /// Key of `factory` on the chain `middleware` is connected to.
+    pub async fn for_factory<M: Middleware>(
+        factory: &UniswapV2Factory,
+        middleware: &M,
+    ) -> Result<CheckpointKey, AMMError<M>> {
+        let chain_id = middleware
+            .get_chainid()
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .as_u64();
+        Ok(CheckpointKey::new(chain_id, factory.address))
+    }
+
+    pub fn file_name(&self, format: CheckpointFormat) -> String {
+        format!(
+            "uniswap_v2_{}_{:?}.{}",
+            self.chain_id,
+            self.factory,
+            format.extension()
+        )
+    }
+}
+
+/// Storage the sync functions read checkpoints from and write them to.
+pub trait CheckpointStore {
+    /// Returns `Ok(None)` when there is no checkpoint for `key`.
+    fn load(&self, key: &CheckpointKey) -> Result<Option<Checkpoint>, CheckpointError>;
+
+    fn save(&self, key: &CheckpointKey, checkpoint: &Checkpoint) -> Result<(), CheckpointError>;
+}
+
+/// Stores each checkpoint as a file in `dir`, created on the first save. Checkpoints are written
+/// as JSON unless another format is set with [`FileCheckpointStore::with_format`].
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    pub dir: PathBuf,
+    pub format: CheckpointFormat,
+}
+
+impl FileCheckpointStore {
+    pub fn new(dir: impl Into<PathBuf>) -> FileCheckpointStore {
+        FileCheckpointStore {
+            dir: dir.into(),
+            format: CheckpointFormat::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: CheckpointFormat) -> FileCheckpointStore {
+        self.format = format;
+        self
+    }
+
+    pub fn path(&self, key: &CheckpointKey) -> PathBuf {
+        self.dir.join(key.file_name(self.format))
+    }
+
+    /// Paths checkpoints were written to before they were keyed by chain, JSON without an
+    /// extension: `uniswap_v2_pairs_<factory>` for each factory of a multi factory sync and
+    /// `uniswap_v2_pairs` for a single factory.
+    fn legacy_paths(&self, key: &CheckpointKey) -> [PathBuf; 2] {
+        [
+            self.dir.join(format!("uniswap_v2_pairs_{:?}", key.factory)),
+            self.dir.join("uniswap_v2_pairs"),
+        ]
+    }
+
+    /// Reads the legacy checkpoint of `key` and moves it to [`FileCheckpointStore::path`]. Legacy
+    /// checkpoints do not record the chain, they are assumed to be for the chain of `key`.
+    fn load_legacy(&self, key: &CheckpointKey) -> Result<Option<Checkpoint>, CheckpointError> {
+        for legacy_path in self.legacy_paths(key) {
+            let checkpoint = match fs::read(&legacy_path) {
+                Ok(bytes) => Checkpoint::from_json(&bytes)?,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if checkpoint.factory.address != key.factory {
+                continue;
+            }
+            self.save(key, &checkpoint)?;
+            fs::remove_file(legacy_path)?;
+            return Ok(Some(checkpoint));
+        }
+        Ok(None)
+    }
+}
+
+impl Default for FileCheckpointStore {
+    /// Stores checkpoints in `checkpoint_data/`, relative to the working directory.
+    fn default() -> Self {
+        FileCheckpointStore::new(DEFAULT_CHECKPOINT_DIR)
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    /// Falls back to the previous checkpoint when the checkpoint is missing or corrupted, which
+    /// happens when a save was interrupted. When neither exists, a checkpoint written under the
+    /// legacy file names is moved to the current one.
+    fn load(&self, key: &CheckpointKey) -> Result<Option<Checkpoint>, CheckpointError> {
+        let path = self.path(key);
+        let err = match Checkpoint::read_from_path(&path) {
+            Ok(checkpoint) => return Ok(Some(checkpoint)),
+            Err(err) if is_missing(&err) || is_corrupted(&err) => err,
+            Err(err) => return Err(err),
+        };
+        match Checkpoint::read_from_path(previous_path(&path)) {
+            Ok(checkpoint) => Ok(Some(checkpoint)),
+            Err(previous_err) if is_missing(&previous_err) => {
+                if is_missing(&err) {
+                    self.load_legacy(key)
+                } else {
+                    Err(err)
+                }
+            }
+            Err(previous_err) => Err(previous_err),
+        }
+    }
+
+    fn save(&self, key: &CheckpointKey, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        fs::create_dir_all(&self.dir)?;
+        checkpoint.save_to_path(self.path(key))
+    }
+}
+
+fn is_missing(err: &CheckpointError) -> bool {
+    matches!(err, CheckpointError::IOError(err) if err.kind() == ErrorKind::NotFound)
+}
+
+/// Whether the checkpoint file is damaged, as opposed to unreadable by this version of the crate.
+fn is_corrupted(err: &CheckpointError) -> bool {
+    matches!(
+        err,
+        CheckpointError::ChecksumMismatch(_)
+            | CheckpointError::SerdeJsonError(_)
+            | CheckpointError::BincodeError(_)
+            | CheckpointError::InvalidBinaryHeader
+    )
+}
+
+/// Keeps checkpoints in memory, for tests and short lived processes. Clones share the same
+/// checkpoints.
+#[derive(Clone, Default)]
+pub struct MemoryCheckpointStore {
+    checkpoints: Arc<Mutex<HashMap<CheckpointKey, Checkpoint>>>,
+}
+
+impl MemoryCheckpointStore {
+    pub fn new() -> MemoryCheckpointStore {
+        MemoryCheckpointStore::default()
+    }
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    fn load(&self, key: &CheckpointKey) -> Result<Option<Checkpoint>, CheckpointError> {
+        Ok(self.checkpoints.lock().unwrap().get(key).cloned())
+    }
+
+    fn save(&self, key: &CheckpointKey, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        self.checkpoints
+            .lock()
+            .unwrap()
+            .insert(*key, checkpoint.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::H256;
+
+    use super::*;
+
+    fn checkpoint(block_number: u64) -> Checkpoint {
+        let pool = UniswapV2Pool {
+            reserve_0: 1 << 100,
+            ..Default::default()
+        };
+        Checkpoint::new(
+            0,
+            block_number,
+            UniswapV2Factory::default(),
+            vec![pool],
+            vec![],
+        )
+    }
+
+    fn assert_round_trip(store: &impl CheckpointStore) {
+        let key = CheckpointKey::new(1, H160::from_low_u64_be(1));
+        let other_chain_key = CheckpointKey::new(10, H160::from_low_u64_be(1));
+
+        assert!(store.load(&key).unwrap().is_none());
+        store.save(&key, &checkpoint(100)).unwrap();
+        store.save(&other_chain_key, &checkpoint(200)).unwrap();
+
+        let loaded = store.load(&key).unwrap().unwrap();
+        assert_eq!((loaded.block_number, loaded.pools.len()), (100, 1));
+        assert_eq!(
+            store.load(&other_chain_key).unwrap().unwrap().block_number,
+            200
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_migrations() {
+        let v1 = r#"{"timestamp": 0, "block_number": 100, "factory": {"address": "0x0000000000000000000000000000000000000001", "creation_block": 0, "fee": 300}, "pools": []}"#;
+        let checkpoint = Checkpoint::from_json(v1.as_bytes()).unwrap();
+        assert_eq!(
+            (
+                checkpoint.version,
+                checkpoint.block_number,
+                checkpoint.rejected.len()
+            ),
+            (CHECKPOINT_VERSION, 100, 0)
+        );
+
+        let newer = format!(r#"{{"version": {}}}"#, CHECKPOINT_VERSION + 1);
+        assert!(matches!(
+            Checkpoint::from_json(newer.as_bytes()),
+            Err(CheckpointError::IncompatibleVersion { found, .. }) if found == CHECKPOINT_VERSION + 1
+        ));
+        assert!(matches!(
+            Checkpoint::from_json(br#"{"version": 2, "pools": 1}"#),
+            Err(CheckpointError::InvalidSchema { version: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_binary_checkpoint_migrations() {
+        let (address, token) = (H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+        let reserve = u128::from(u64::MAX) + 1;
+        // The version 2 layout, `H160` without `raw_bytes` is written as a hex string.
+        let pool = (
+            address, token, 18u8, token, 6u8, reserve, 1u128, 7u32, 100u64, 300u32,
+        );
+        let creation = Some((90u64, H256::from_low_u64_be(3), 4u64, 5u64));
+        let v2 = (
+            (2u32, 0usize, 100u64, (address, 0u64, 300u32)),
+            vec![(pool, creation, address)],
+            vec![(token, (2u32, token))],
+        );
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bincode::serialize_into(&mut bytes, &v2).unwrap();
+
+        let checkpoint = Checkpoint::decode(&bytes, CheckpointFormat::Binary).unwrap();
+        assert_eq!(checkpoint.version, CHECKPOINT_VERSION);
+        assert_eq!(checkpoint.factory.address, address);
+        let pool = &checkpoint.pools[0];
+        assert_eq!((pool.token_b, pool.reserve_0), (token, reserve));
+        assert_eq!(
+            pool.creation.unwrap().transaction_hash,
+            H256::from_low_u64_be(3)
+        );
+        assert_eq!(
+            checkpoint.rejected,
+            vec![(token, RejectReason::MissingTokenCode(token))]
+        );
+
+        // The 7 addresses and the hash are raw bytes in the current layout, instead of a length
+        // prefixed hex string of 8 + 42 and 8 + 66 bytes.
+        let current = checkpoint.encode(CheckpointFormat::Binary).unwrap();
+        assert_eq!(bytes.len() - current.len(), 7 * 30 + 42);
+        let decoded = Checkpoint::decode(&current, CheckpointFormat::Binary).unwrap();
+        assert_eq!(decoded.pools[0].reserve_0, reserve);
+        assert_eq!(decoded.rejected, checkpoint.rejected);
+    }
+
+    #[test]
+    fn test_checkpoint_formats() {
+        for format in [
+            CheckpointFormat::Json,
+            CheckpointFormat::Binary,
+            CheckpointFormat::CompressedBinary,
+        ] {
+            let bytes = checkpoint(100).encode(format).unwrap();
+            let decoded = Checkpoint::decode(&bytes, format).unwrap();
+            assert_eq!((decoded.block_number, decoded.pools.len()), (100, 1));
+            assert_eq!(decoded.pools[0].reserve_0, 1 << 100);
+            assert_eq!(
+                CheckpointFormat::from_path(format!("checkpoint.{}", format.extension())),
+                format
+            );
+        }
+        let json = checkpoint(100).encode(CheckpointFormat::Json).unwrap();
+        assert!(matches!(
+            Checkpoint::decode(&json, CheckpointFormat::Binary),
+            Err(CheckpointError::InvalidBinaryHeader)
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_falls_back_to_previous() {
+        let dir = std::env::temp_dir().join(format!("amm_previous_{}", std::process::id()));
+        let store = FileCheckpointStore::new(&dir);
+        let key = CheckpointKey::new(1, H160::from_low_u64_be(1));
+        store.save(&key, &checkpoint(100)).unwrap();
+        store.save(&key, &checkpoint(200)).unwrap();
+        assert_eq!(store.load(&key).unwrap().unwrap().block_number, 200);
+
+        // A write cut short by a crash.
+        let bytes = fs::read(store.path(&key)).unwrap();
+        fs::write(store.path(&key), &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(
+            Checkpoint::read_from_path(store.path(&key)),
+            Err(CheckpointError::ChecksumMismatch(_))
+        ));
+        assert_eq!(store.load(&key).unwrap().unwrap().block_number, 100);
+
+        // The corrupted checkpoint does not replace the previous one.
+        store.save(&key, &checkpoint(300)).unwrap();
+        assert_eq!(store.load(&key).unwrap().unwrap().block_number, 300);
+        let previous = Checkpoint::read_from_path(previous_path(store.path(&key))).unwrap();
+        assert_eq!(previous.block_number, 100);
+
+        fs::remove_file(store.path(&key)).unwrap();
+        assert_eq!(store.load(&key).unwrap().unwrap().block_number, 100);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_moves_legacy_files() {
+        let dir = std::env::temp_dir().join(format!("amm_legacy_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = FileCheckpointStore::new(&dir);
+        let factory = UniswapV2Factory::new(H160::from_low_u64_be(1), 0, 300);
+        let key = CheckpointKey::new(1, factory.address);
+        let legacy = Checkpoint::new(0, 100, factory, vec![], vec![]);
+        fs::write(
+            dir.join("uniswap_v2_pairs"),
+            legacy.encode(CheckpointFormat::Json).unwrap(),
+        )
+        .unwrap();
+
+        // The unkeyed file belongs to another factory.
+        let other_key = CheckpointKey::new(1, H160::from_low_u64_be(2));
+        assert!(store.load(&other_key).unwrap().is_none());
+
+        assert_eq!(store.load(&key).unwrap().unwrap().block_number, 100);
+        assert!(!dir.join("uniswap_v2_pairs").exists());
+        assert_eq!(
+            Checkpoint::read_from_path(store.path(&key))
+                .unwrap()
+                .block_number,
+            100
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_stores() {
+        assert_round_trip(&MemoryCheckpointStore::new());
+
+        let dir = std::env::temp_dir().join(format!("amm_checkpoints_{}", std::process::id()));
+        assert_round_trip(&FileCheckpointStore::new(&dir));
+        assert_round_trip(
+            &FileCheckpointStore::new(&dir).with_format(CheckpointFormat::CompressedBinary),
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,15 +1,21 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use super::{batch_request, UniswapV2Pool};
-use crate::errors::AMMError;
+use super::{batch_request, batch_request::RejectReason, checkpoint::raw_bytes, UniswapV2Pool};
+use crate::{
+    batch_request::resolve_block_number,
+    errors::{AMMError, EventLogError},
+};
 use ethers::prelude::abigen;
 use ethers::{
     abi::RawLog,
     prelude::EthEvent,
     providers::Middleware,
-    types::{BlockNumber, Filter, ValueOrArray, H160, H256, U256, U64},
+    types::{BlockId, BlockNumber, Filter, ValueOrArray, H160, H256, U256, U64},
 };
-use futures::future;
+use futures::{future, stream, Stream, StreamExt};
 use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
 
@@ -20,6 +26,8 @@ abigen!(
         function allPairs(uint256 index) external view returns (address)
         event PairCreated(address indexed token0, address indexed token1, address pair, uint256)
         function allPairsLength() external view returns (uint256)
+        function feeTo() external view returns (address)
+        function feeToSetter() external view returns (address)
 
     ]"#;
 );
@@ -29,8 +37,38 @@ pub const PAIR_CREATED_EVENT_SIGNATURE: H256 = H256([
     131, 85, 205, 222, 253, 227, 26, 250, 40, 208, 233,
 ]);
 
+/// Maximum number of `allPairs` ranges fetched concurrently by [`UniswapV2Factory::stream_all_pools`].
+pub const POOL_DISCOVERY_CONCURRENCY: usize = 32;
+
+/// A batch of pools discovered from the factory, covering the `allPairs` indices `from..to`.
+///
+/// Batches are yielded in index order, so once a batch has been received every index below
+/// `to` has been fetched and `to` can be persisted as the index to resume from. All batches of a
+/// stream are read at the same `block_number`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolBatch {
+    pub from: u128,
+    pub to: u128,
+    pub block_number: u64,
+    pub pools: Vec<UniswapV2Pool>,
+    pub rejected: Vec<(H160, RejectReason)>,
+}
+
+/// Where and when a pool was created, taken from the factory `PairCreated` log.
+///
+/// `pair_index` is the pool's position in the factory `allPairs` array.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolCreation {
+    pub block_number: u64,
+    #[serde(with = "raw_bytes")]
+    pub transaction_hash: H256,
+    pub log_index: u64,
+    pub pair_index: u64,
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct UniswapV2Factory {
+    #[serde(with = "raw_bytes")]
     pub address: H160,
     pub creation_block: u64,
     pub fee: u32,
@@ -66,12 +104,32 @@ impl UniswapV2Factory {
             .unwrap()
     }
 
-    async fn get_pool_addresses_from_logs_range<M: Middleware>(
+    pub async fn get_fee_to<M: Middleware>(&self, middleware: Arc<M>) -> Result<H160, AMMError<M>> {
+        Ok(self.contract(middleware).fee_to().call().await?)
+    }
+
+    pub async fn get_fee_to_setter<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<H160, AMMError<M>> {
+        Ok(self.contract(middleware).fee_to_setter().call().await?)
+    }
+
+    /// The protocol fee is on whenever `feeTo` is set, in which case a sixth of the LP fee growth
+    /// is minted to `feeTo` on the next mint or burn of each pair.
+    pub async fn protocol_fee_on<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<bool, AMMError<M>> {
+        Ok(!self.get_fee_to(middleware).await?.is_zero())
+    }
+
+    async fn get_pool_creations_from_logs_range<M: Middleware>(
         &self,
         start_block: u64,
         end_block: u64,
         middleware: Arc<M>,
-    ) -> Result<Vec<H160>, AMMError<M>> {
+    ) -> Result<Vec<(H160, PoolCreation)>, AMMError<M>> {
         let logs = middleware
             .get_logs(
                 &Filter::new()
@@ -83,22 +141,56 @@ impl UniswapV2Factory {
             .await
             .map_err(AMMError::MiddlewareError)?;
 
-        let mut addresses = vec![];
+        let mut creations = vec![];
         for log in logs {
+            let block_number = log
+                .block_number
+                .ok_or(EventLogError::LogBlockNumberNotFound)?
+                .as_u64();
+            let transaction_hash = log
+                .transaction_hash
+                .ok_or(EventLogError::LogTransactionHashNotFound)?;
+            let log_index = log
+                .log_index
+                .ok_or(EventLogError::LogIndexNotFound)?
+                .as_u64();
             let pair_created_event: PairCreatedFilter =
                 PairCreatedFilter::decode_log(&RawLog::from(log))?;
-            addresses.push(pair_created_event.pair);
+            // The event carries `allPairs.length` after the pair was pushed.
+            let pair_index = u64::try_from(pair_created_event.p3)
+                .ok()
+                .and_then(|length| length.checked_sub(1))
+                .ok_or(EventLogError::InvalidPairIndex(pair_created_event.p3))?;
+            creations.push((
+                pair_created_event.pair,
+                PoolCreation {
+                    block_number,
+                    transaction_hash,
+                    log_index,
+                    pair_index,
+                },
+            ));
         }
-        Ok(addresses)
+        Ok(creations)
     }
 
     async fn get_pools_from_addresses<M: Middleware>(
         &self,
         middleware: Arc<M>,
         addresses: Vec<H160>,
-    ) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
-        batch_request::get_uniswap_v2_pool_data_batch_request(&addresses, self.fee, middleware)
-            .await
+        block_number: u64,
+    ) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>), AMMError<M>> {
+        let (mut pools, rejected) = batch_request::get_uniswap_v2_pool_data_batch_request(
+            &addresses,
+            self.fee,
+            middleware,
+            Some(block_number.into()),
+        )
+        .await?;
+        for pool in pools.iter_mut() {
+            pool.factory = self.address;
+        }
+        Ok((pools, rejected))
     }
 
     async fn get_pools_from_logs_range<M: Middleware>(
@@ -107,18 +199,30 @@ impl UniswapV2Factory {
         end_block: u64,
         middleware: Arc<M>,
         progress_bar: Option<Arc<Mutex<ProgressBar>>>,
-    ) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
-        let addresses = self
-            .get_pool_addresses_from_logs_range(start_block, end_block, middleware.clone())
+        block_number: u64,
+    ) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>), AMMError<M>> {
+        let creations: HashMap<H160, PoolCreation> = self
+            .get_pool_creations_from_logs_range(start_block, end_block, middleware.clone())
+            .await?
+            .into_iter()
+            .collect();
+        let (mut pairs, rejected) = self
+            .get_pools_from_addresses(
+                middleware,
+                creations.keys().copied().collect(),
+                block_number,
+            )
             .await?;
-        let pairs: Vec<UniswapV2Pool> =
-            self.get_pools_from_addresses(middleware, addresses).await?;
+        for pair in pairs.iter_mut() {
+            pair.creation = creations.get(&pair.address).copied();
+        }
+        pairs.sort_by_key(|pair| pair.creation.map(|creation| creation.pair_index));
 
         if let Some(progress_bar) = progress_bar {
             progress_bar.lock().unwrap().inc(end_block - start_block);
         }
 
-        Ok(pairs)
+        Ok((pairs, rejected))
     }
 
     async fn get_pool_addresses_range<M: Middleware>(
@@ -126,12 +230,14 @@ impl UniswapV2Factory {
         middleware: Arc<M>,
         from: u128,
         to: u128,
+        block_number: u64,
     ) -> Result<Vec<H160>, AMMError<M>> {
         batch_request::get_uniswap_v2_pairs_batch_request(
             self.address,
             U256::from(from),
             U256::from(to),
             middleware,
+            Some(block_number.into()),
         )
         .await
     }
@@ -141,78 +247,118 @@ impl UniswapV2Factory {
         middleware: Arc<M>,
         from: u128,
         to: u128,
-        progress_bar: Option<Arc<Mutex<ProgressBar>>>,
-    ) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
+        block_number: u64,
+    ) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>), AMMError<M>> {
         let addresses = self
-            .get_pool_addresses_range(middleware.clone(), from, to)
+            .get_pool_addresses_range(middleware.clone(), from, to, block_number)
             .await?;
-        let pairs = batch_request::get_uniswap_v2_pool_data_batch_request(
-            &addresses,
-            self.fee,
-            middleware.clone(),
-        )
-        .await?;
+        self.get_pools_from_addresses(middleware, addresses, block_number)
+            .await
+    }
 
-        if let Some(progress_bar) = progress_bar {
-            let locked_pb = progress_bar.lock().unwrap();
-            locked_pb.inc((to - from) as u64);
+    pub async fn all_pairs_length<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+        block: Option<BlockId>,
+    ) -> Result<u128, AMMError<M>> {
+        let contract = self.contract(middleware);
+        let mut call = contract.all_pairs_length();
+        if let Some(block) = block {
+            call = call.block(block);
         }
-        Ok(pairs)
+        let pairs_length: U256 = call.call().await?;
+        Ok(pairs_length.as_u128())
     }
 
+    /// Streams the factory pools in batches of `step` `allPairs` indices, starting at `from_index`.
+    ///
+    /// Returns the stream together with the `allPairs` length it runs up to. Batches are fetched
+    /// concurrently but yielded in order, see [`PoolBatch`] for resuming an interrupted run. The
+    /// whole stream is read at `block`, which defaults to the current block.
+    pub async fn stream_all_pools<'a, M: Middleware + 'a>(
+        &'a self,
+        middleware: Arc<M>,
+        from_index: Option<u128>,
+        step: Option<usize>,
+        block: Option<BlockId>,
+    ) -> Result<
+        (
+            impl Stream<Item = Result<PoolBatch, AMMError<M>>> + 'a,
+            u128,
+        ),
+        AMMError<M>,
+    > {
+        let from_index = from_index.unwrap_or(0);
+        let step = step.unwrap_or(100).max(1);
+        let block_number = resolve_block_number(middleware.clone(), block).await?;
+        let pairs_length = self
+            .all_pairs_length(middleware.clone(), Some(block_number.into()))
+            .await?;
+
+        let ranges: Vec<(u128, u128)> = (from_index.min(pairs_length)..pairs_length)
+            .step_by(step)
+            .map(|from| (from, (from + step as u128).min(pairs_length)))
+            .collect();
+
+        let pool_batches = stream::iter(ranges)
+            .map(move |(from, to)| {
+                let middleware = middleware.clone();
+                async move {
+                    let (pools, rejected) = self
+                        .get_pools_range(middleware, from, to, block_number)
+                        .await?;
+                    Ok(PoolBatch {
+                        from,
+                        to,
+                        block_number,
+                        pools,
+                        rejected,
+                    })
+                }
+            })
+            .buffered(POOL_DISCOVERY_CONCURRENCY);
+
+        Ok((pool_batches, pairs_length))
+    }
+
+    /// Fetches every factory pool at `block`, defaulting to the current block, and returns them
+    /// along with the block number they were read at.
     pub async fn get_all_pools<M: Middleware>(
         &self,
         middleware: Arc<M>,
         step: Option<usize>,
-    ) -> Result<(Vec<UniswapV2Pool>, u64), AMMError<M>> {
-        let step = match step {
-            Some(step) => step,
-            None => 100,
-        };
-        let current_block = middleware
-            .get_block_number()
-            .await
-            .map_err(AMMError::MiddlewareError)?
-            .as_u64();
-        let pairs_length: U256 = self
-            .contract(middleware.clone())
-            .all_pairs_length()
-            .call()
+        block: Option<BlockId>,
+    ) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>, u64), AMMError<M>> {
+        let block_number = resolve_block_number(middleware.clone(), block).await?;
+        let (pool_batches, pairs_length) = self
+            .stream_all_pools(middleware.clone(), None, step, Some(block_number.into()))
             .await?;
 
         println!("Syncing {} uniswap pools", pairs_length);
-        let pb = ProgressBar::new(pairs_length.as_u64());
-        let shared_pb = Arc::new(Mutex::new(pb));
-
-        let mut futures: Vec<_> = vec![];
-        for i in (0..pairs_length.as_u128()).step_by(step) {
-            futures.push(self.get_pools_range(
-                middleware.clone(),
-                i,
-                (i + step as u128).min(pairs_length.as_u128()),
-                Some(shared_pb.clone()),
-            ));
-        }
+        let pb = ProgressBar::new(pairs_length as u64);
 
-        let results: Vec<Result<Vec<UniswapV2Pool>, AMMError<M>>> = future::join_all(futures).await;
+        futures::pin_mut!(pool_batches);
         let mut pools = Vec::new();
-        for result in results {
-            match result {
-                Ok(mut pool_batch) => pools.append(&mut pool_batch),
-                Err(err) => return Err(err),
-            }
+        let mut rejected = Vec::new();
+        while let Some(pool_batch) = pool_batches.next().await {
+            let mut pool_batch = pool_batch?;
+            pb.inc((pool_batch.to - pool_batch.from) as u64);
+            pools.append(&mut pool_batch.pools);
+            rejected.append(&mut pool_batch.rejected);
         }
-        shared_pb.lock().unwrap().finish();
-        Ok((pools, current_block))
+        pb.finish();
+        Ok((pools, rejected, block_number))
     }
 
+    /// Fetches the pools created between `start_block` and `end_block`, with their data read at
+    /// `end_block`. Returns them along with `end_block`, which defaults to the current block.
     pub async fn get_pools_from_logs<M: Middleware>(
         &self,
         middleware: Arc<M>,
         start_block: Option<u64>,
         end_block: Option<u64>,
         step: Option<usize>,
-    ) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
+    ) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>, u64), AMMError<M>> {
         let start_block = match start_block {
             Some(start_block) => start_block,
             None => 0,
@@ -225,10 +371,7 @@ impl UniswapV2Factory {
                 .map_err(AMMError::MiddlewareError)?
                 .as_u64(),
         };
-        let step = match step {
-            Some(step) => step,
-            None => 100,
-        };
+        let step = step.unwrap_or(100).max(1);
         let total_blocks = end_block - start_block;
 
         println!("Syncing uniswap pools for {} blocks", total_blocks);
@@ -242,15 +385,20 @@ impl UniswapV2Factory {
                 (i + step as u64).min(end_block),
                 middleware.clone(),
                 Some(shared_pb.clone()),
+                end_block,
             ));
         }
 
-        let results: Vec<Result<Vec<UniswapV2Pool>, AMMError<M>>> = future::join_all(futures).await;
+        let results = future::join_all(futures).await;
 
         let mut pools = Vec::new();
+        let mut rejected = Vec::new();
         for result in results {
             match result {
-                Ok(mut pool_batch) => pools.append(&mut pool_batch),
+                Ok((mut pool_batch, mut rejected_batch)) => {
+                    pools.append(&mut pool_batch);
+                    rejected.append(&mut rejected_batch);
+                }
                 Err(AMMError::PoolDataError(addr)) => {
                     println!("Data not populated for {:?}", addr);
                 }
@@ -260,6 +408,81 @@ impl UniswapV2Factory {
 
         shared_pb.lock().unwrap().finish();
 
-        Ok(pools)
+        Ok((pools, rejected, end_block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethers::{
+        abi::{encode, Token},
+        providers::{Http, Provider},
+        types::Log,
+    };
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn test_pool_creation_with_zero_pair_index() {
+        let (provider, mock) = Provider::mocked();
+        let factory = UniswapV2Factory::new(H160::from_low_u64_be(1), 0, 300);
+        let log = Log {
+            address: factory.address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(2)),
+                H256::from(H160::from_low_u64_be(3)),
+            ],
+            data: encode(&[
+                Token::Address(H160::from_low_u64_be(4)),
+                Token::Uint(U256::zero()),
+            ])
+            .into(),
+            block_number: Some(1.into()),
+            transaction_hash: Some(H256::from_low_u64_be(5)),
+            log_index: Some(0.into()),
+            ..Default::default()
+        };
+        mock.push::<Vec<Log>, _>(vec![log]).unwrap();
+
+        let result = factory
+            .get_pool_creations_from_logs_range(1, 1, Arc::new(provider))
+            .await;
+        assert!(matches!(
+            result,
+            Err(AMMError::EventLogError(EventLogError::InvalidPairIndex(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pair_index_from_pair_created_log() {
+        dotenv::dotenv().ok();
+        let rpc_endpoint = std::env::var("NETWORK_RPC").expect("Missing NETWORK_RPC env variable");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+        let factory = UniswapV2Factory::new(
+            H160::from_str("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f").unwrap(),
+            10000835,
+            300,
+        );
+        // USDC/WETH, created in block 10008355
+        let pair = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
+
+        let creations = factory
+            .get_pool_creations_from_logs_range(10008355, 10008355, middleware.clone())
+            .await
+            .unwrap();
+        let (_, creation) = creations
+            .into_iter()
+            .find(|(address, _)| *address == pair)
+            .unwrap();
+
+        let all_pairs = factory
+            .contract(middleware)
+            .all_pairs(U256::from(creation.pair_index))
+            .call()
+            .await
+            .unwrap();
+        assert_eq!(all_pairs, pair);
     }
 }
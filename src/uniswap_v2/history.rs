@@ -0,0 +1,342 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use ethers::{
+    providers::{Middleware, MiddlewareError},
+    types::{Filter, Log, H160},
+};
+use futures::{stream, StreamExt, TryStreamExt};
+
+use super::{
+    batch_request::refresh_reserves, checkpoint::Checkpoint, UniswapV2Pool, SYNC_EVENT_SIGNATURE,
+};
+use crate::errors::{AMMError, EventLogError};
+
+/// Pools per `eth_getLogs` address filter when replaying `Sync` logs.
+const ADDRESSES_PER_LOG_REQUEST: usize = 1000;
+
+/// Maximum number of `eth_getLogs` and `eth_getBlockByNumber` requests in flight when replaying
+/// `Sync` logs.
+pub const LOG_REQUEST_CONCURRENCY: usize = 16;
+
+/// Number of logs most providers cap an `eth_getLogs` response at, either failing the request or
+/// truncating the result.
+const MAX_LOGS_PER_REQUEST: usize = 10_000;
+
+/// Rebuilds `pools` as they were at the end of `block_number`, starting from the reserves each
+/// pool holds at `pool.block_number` and replaying the `Sync` logs emitted after it, fetched
+/// `step` blocks at a time, 1000 by default and at least 1. Ranges with more logs than the node
+/// returns in one response are split until they fit.
+///
+/// Pools whose reserves are newer than `block_number`, or fetched at an unknown block, are first
+/// reset with a batch fetch pinned to `block_number`, which needs an archive node for old blocks.
+/// Returns the rebuilt pools, whose `block_number` is `block_number`, along with the pools whose
+/// reserves could not be fetched at that block, which are left out.
+pub async fn pools_at_block<M: Middleware>(
+    pools: Vec<UniswapV2Pool>,
+    block_number: u64,
+    middleware: Arc<M>,
+    step: Option<u64>,
+) -> Result<(Vec<UniswapV2Pool>, Vec<H160>), AMMError<M>> {
+    let step = step.unwrap_or(1000).max(1);
+    let (mut replayed, mut pinned): (Vec<UniswapV2Pool>, Vec<UniswapV2Pool>) = pools
+        .into_iter()
+        .partition(|pool| pool.block_number != 0 && pool.block_number <= block_number);
+    let mut failed = vec![];
+    if !pinned.is_empty() {
+        failed =
+            refresh_reserves(&mut pinned, middleware.clone(), Some(block_number.into())).await?;
+        pinned.retain(|pool| !failed.contains(&pool.address));
+    }
+
+    let from_block = replayed.iter().map(|pool| pool.block_number + 1).min();
+    if let Some(from_block) = from_block.filter(|from_block| *from_block <= block_number) {
+        let addresses: Vec<H160> = replayed.iter().map(|pool| pool.address).collect();
+        let ranges = addresses
+            .chunks(ADDRESSES_PER_LOG_REQUEST)
+            .flat_map(|addresses| {
+                (from_block..=block_number)
+                    .step_by(step as usize)
+                    .map(move |start_block| {
+                        let end_block = start_block.saturating_add(step - 1).min(block_number);
+                        (addresses, start_block, end_block)
+                    })
+            });
+        let logs: Vec<Log> = stream::iter(ranges)
+            .map(|(addresses, start_block, end_block)| {
+                get_sync_logs(middleware.as_ref(), addresses, start_block, end_block)
+            })
+            .buffer_unordered(LOG_REQUEST_CONCURRENCY)
+            .try_concat()
+            .await?;
+
+        let updated_at = replay_sync_logs(&mut replayed, logs)?;
+        // `blockTimestampLast` is not part of the log, it is the timestamp of the block of the
+        // last update.
+        let blocks: HashSet<u64> = updated_at.values().copied().collect();
+        let timestamps: HashMap<u64, u32> = stream::iter(blocks)
+            .map(|block| {
+                let middleware = middleware.clone();
+                async move {
+                    let timestamp = middleware
+                        .get_block(block)
+                        .await
+                        .map_err(AMMError::MiddlewareError)?
+                        .ok_or(AMMError::BlockNumberNotFound)?
+                        .timestamp;
+                    Ok::<_, AMMError<M>>((block, timestamp.low_u32()))
+                }
+            })
+            .buffer_unordered(LOG_REQUEST_CONCURRENCY)
+            .try_collect()
+            .await?;
+        for pool in replayed.iter_mut() {
+            if let Some(block) = updated_at.get(&pool.address) {
+                pool.block_timestamp_last = timestamps[block];
+            }
+        }
+    }
+
+    for pool in replayed.iter_mut() {
+        pool.block_number = block_number;
+    }
+    replayed.append(&mut pinned);
+    Ok((replayed, failed))
+}
+
+/// Rebuilds the pools of `checkpoint` that existed at the end of `block_number`, see
+/// [`pools_at_block`]. Pools without a known creation block are always included.
+pub async fn checkpoint_pools_at_block<M: Middleware>(
+    checkpoint: &Checkpoint,
+    block_number: u64,
+    middleware: Arc<M>,
+    step: Option<u64>,
+) -> Result<(Vec<UniswapV2Pool>, Vec<H160>), AMMError<M>> {
+    let pools = checkpoint
+        .pools
+        .iter()
+        .filter(|pool| {
+            pool.creation
+                .is_none_or(|creation| creation.block_number <= block_number)
+        })
+        .cloned()
+        .collect();
+    pools_at_block(pools, block_number, middleware, step).await
+}
+
+/// Compares `pools` with their reserves fetched at `block_number`, returning the pools whose
+/// reserves or `blockTimestampLast` differ or could not be fetched.
+pub async fn verify_pools_at_block<M: Middleware>(
+    pools: &[UniswapV2Pool],
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<H160>, AMMError<M>> {
+    let mut fetched = pools.to_vec();
+    let mut mismatched =
+        refresh_reserves(&mut fetched, middleware, Some(block_number.into())).await?;
+    for (pool, fetched) in pools.iter().zip(&fetched) {
+        let matches = (pool.reserve_0, pool.reserve_1, pool.block_timestamp_last)
+            == (
+                fetched.reserve_0,
+                fetched.reserve_1,
+                fetched.block_timestamp_last,
+            );
+        if !matches && !mismatched.contains(&pool.address) {
+            mismatched.push(pool.address);
+        }
+    }
+    Ok(mismatched)
+}
+
+/// Fetches the `Sync` logs of `addresses` from `from_block` to `to_block`, halving the range while
+/// the node rejects the request or returns [`MAX_LOGS_PER_REQUEST`] logs, which may be truncated.
+async fn get_sync_logs<M: Middleware>(
+    middleware: &M,
+    addresses: &[H160],
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Log>, AMMError<M>> {
+    let mut logs = vec![];
+    let mut ranges = vec![(from_block, to_block)];
+    while let Some((from_block, to_block)) = ranges.pop() {
+        let filter = Filter::new()
+            .address(addresses.to_vec())
+            .topic0(SYNC_EVENT_SIGNATURE)
+            .from_block(from_block)
+            .to_block(to_block);
+        let can_split = from_block < to_block;
+        match middleware.get_logs(&filter).await {
+            Ok(range_logs) if range_logs.len() >= MAX_LOGS_PER_REQUEST && can_split => {}
+            Ok(mut range_logs) => {
+                logs.append(&mut range_logs);
+                continue;
+            }
+            Err(err) if err.is_error_response() && can_split => {}
+            Err(err) => return Err(AMMError::MiddlewareError(err)),
+        }
+        let middle = from_block + (to_block - from_block) / 2;
+        ranges.push((middle + 1, to_block));
+        ranges.push((from_block, middle));
+    }
+    Ok(logs)
+}
+
+/// Applies the `Sync` logs emitted after the block of each pool's reserves, in chain order.
+/// Returns the block of the last applied log of each updated pool.
+fn replay_sync_logs(
+    pools: &mut [UniswapV2Pool],
+    mut logs: Vec<Log>,
+) -> Result<HashMap<H160, u64>, EventLogError> {
+    logs.sort_by_key(|log| (log.block_number, log.log_index));
+    let mut pools: HashMap<H160, (u64, &mut UniswapV2Pool)> = pools
+        .iter_mut()
+        .map(|pool| (pool.address, (pool.block_number, pool)))
+        .collect();
+    let mut updated_at = HashMap::new();
+    for log in logs {
+        let log_block = log
+            .block_number
+            .ok_or(EventLogError::LogBlockNumberNotFound)?
+            .as_u64();
+        if log.removed == Some(true) {
+            continue;
+        }
+        if let Some((snapshot_block, pool)) = pools.get_mut(&log.address) {
+            if log_block > *snapshot_block {
+                updated_at.insert(log.address, log_block);
+                pool.sync_from_log(log)?;
+            }
+        }
+    }
+    Ok(updated_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, Token},
+        types::U256,
+    };
+
+    use super::*;
+
+    use ethers::providers::{Http, JsonRpcError, MockResponse, Provider};
+    use std::str::FromStr;
+
+    fn sync_log(address: H160, block_number: u64, log_index: u64, reserve: u64) -> Log {
+        Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: encode(&[
+                Token::Uint(U256::from(reserve)),
+                Token::Uint(U256::from(reserve)),
+            ])
+            .into(),
+            block_number: Some(block_number.into()),
+            log_index: Some(log_index.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_replay_sync_logs() {
+        let (a, b) = (H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+        let mut pools = vec![
+            UniswapV2Pool {
+                address: a,
+                reserve_0: 1,
+                block_number: 10,
+                ..Default::default()
+            },
+            UniswapV2Pool {
+                address: b,
+                reserve_0: 2,
+                block_number: 12,
+                ..Default::default()
+            },
+        ];
+        let logs = vec![
+            sync_log(a, 12, 1, 30),
+            sync_log(a, 12, 0, 20),
+            sync_log(a, 10, 0, 10),
+            sync_log(b, 11, 0, 40),
+        ];
+
+        let updated_at = replay_sync_logs(&mut pools, logs).unwrap();
+
+        assert_eq!(updated_at, HashMap::from([(a, 12)]));
+        assert_eq!((pools[0].reserve_0, pools[1].reserve_0), (30, 2));
+    }
+
+    #[tokio::test]
+    async fn test_get_sync_logs_splits_rejected_ranges() {
+        let (provider, mock) = Provider::mocked();
+        let pool = H160::from_low_u64_be(1);
+        // Responses are popped from the back, the first request gets the last one pushed.
+        mock.push::<Vec<Log>, _>(vec![sync_log(pool, 8, 0, 2)])
+            .unwrap();
+        mock.push::<Vec<Log>, _>(vec![sync_log(pool, 2, 0, 1)])
+            .unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data: None,
+        }));
+
+        let logs = get_sync_logs(&provider, &[pool], 0, 9).await.unwrap();
+        let blocks: Vec<u64> = logs
+            .iter()
+            .map(|log| log.block_number.unwrap().as_u64())
+            .collect();
+        assert_eq!(blocks, vec![2, 8]);
+        for (from_block, to_block) in [(0, 9), (0, 4), (5, 9)] {
+            let filter = Filter::new()
+                .address(vec![pool])
+                .topic0(SYNC_EVENT_SIGNATURE)
+                .from_block(from_block)
+                .to_block(to_block);
+            mock.assert_request("eth_getLogs", [filter]).unwrap();
+        }
+
+        // A single block cannot be split further.
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data: None,
+        }));
+        assert!(get_sync_logs(&provider, &[pool], 5, 5).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pools_at_block_match_get_reserves() {
+        dotenv::dotenv().ok();
+        let rpc_endpoint = std::env::var("NETWORK_RPC").expect("Missing NETWORK_RPC env variable");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+        let pool_address = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
+        let snapshot_block = middleware.get_block_number().await.unwrap().as_u64() - 300;
+
+        let mut pools =
+            vec![
+                UniswapV2Pool::new_from_address(pool_address, 300, middleware.clone())
+                    .await
+                    .unwrap(),
+            ];
+        refresh_reserves(&mut pools, middleware.clone(), Some(snapshot_block.into()))
+            .await
+            .unwrap();
+        let (pools, failed) =
+            pools_at_block(pools, snapshot_block + 200, middleware.clone(), Some(50))
+                .await
+                .unwrap();
+
+        assert!(failed.is_empty());
+        assert!(
+            verify_pools_at_block(&pools, snapshot_block + 200, middleware)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -1,19 +1,24 @@
 pub mod batch_request;
+pub mod checkpoint;
 pub mod factory;
+pub mod history;
+pub mod router;
+pub mod subscription;
 pub mod sync;
 
 use std::sync::Arc;
 
 use ethers::{
-    abi::{Bytes, Token},
-    prelude::abigen,
+    abi::{Bytes, RawLog, Token},
+    prelude::{abigen, EthEvent},
     providers::Middleware,
-    types::{H160, U256},
+    types::{spoof, Log, H160, H256, U256},
 };
 use serde::{Deserialize, Serialize};
 
+use self::{checkpoint::raw_bytes, factory::PoolCreation};
 use crate::{
-    errors::{AMMError, ArithmeticError, SwapSimulationError},
+    errors::{AMMError, ArithmeticError, EventLogError, StateOverrideError, SwapSimulationError},
     large_int_maths::{div_uu, q64_to_f64, U128_0X10000000000000000},
 };
 
@@ -24,6 +29,8 @@ abigen!(
         function token0() external view returns (address)
         function token1() external view returns (address)
         function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data);
+        function kLast() external view returns (uint256)
+        function totalSupply() external view returns (uint256)
         event Sync(uint112 reserve0, uint112 reserve1)
     ]"#;
 
@@ -34,16 +41,60 @@ abigen!(
     ]"#;
 );
 
+pub const SYNC_EVENT_SIGNATURE: H256 = H256([
+    28, 65, 30, 154, 150, 224, 113, 36, 28, 47, 33, 247, 114, 107, 23, 174, 137, 227, 202, 180,
+    199, 139, 229, 14, 6, 43, 3, 169, 255, 251, 186, 209,
+]);
+
+/// Storage slot of the pair holding `reserve0`, `reserve1` and `blockTimestampLast`, packed as
+/// `reserve0 | reserve1 << 112 | blockTimestampLast << 224`.
+pub const RESERVES_STORAGE_SLOT: u64 = 8;
+
+/// Packs reserves the way the pair stores them in [`RESERVES_STORAGE_SLOT`].
+pub fn pack_reserves(
+    reserve_0: u128,
+    reserve_1: u128,
+    block_timestamp_last: u32,
+) -> Result<H256, StateOverrideError> {
+    let max_reserve = (1u128 << 112) - 1;
+    for reserve in [reserve_0, reserve_1] {
+        if reserve > max_reserve {
+            return Err(StateOverrideError::ReserveOverflow(reserve));
+        }
+    }
+
+    let packed = U256::from(reserve_0)
+        | U256::from(reserve_1) << 112
+        | U256::from(block_timestamp_last) << 224;
+    let mut value = [0u8; 32];
+    packed.to_big_endian(&mut value);
+    Ok(H256(value))
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UniswapV2Pool {
+    #[serde(with = "raw_bytes")]
     pub address: H160,
+    #[serde(with = "raw_bytes")]
     pub token_a: H160,
     pub token_a_decimals: u8,
+    #[serde(with = "raw_bytes")]
     pub token_b: H160,
     pub token_b_decimals: u8,
     pub reserve_0: u128,
     pub reserve_1: u128,
+    /// `blockTimestampLast` of the pair, the timestamp of the block that last updated the reserves
+    /// modulo 2**32.
+    #[serde(default)]
+    pub block_timestamp_last: u32,
+    /// Block at which the reserves were fetched.
+    #[serde(default)]
+    pub block_number: u64,
     pub fee: u32,
+    #[serde(default)]
+    pub creation: Option<PoolCreation>,
+    #[serde(default, with = "raw_bytes")]
+    pub factory: H160,
 }
 
 impl UniswapV2Pool {
@@ -66,7 +117,11 @@ impl UniswapV2Pool {
             token_b_decimals,
             reserve_0,
             reserve_1,
+            block_timestamp_last: 0,
+            block_number: 0,
             fee,
+            creation: None,
+            factory: H160::zero(),
         }
     }
 
@@ -86,6 +141,59 @@ impl UniswapV2Pool {
         Ok((r0, r1))
     }
 
+    /// Returns the LP `totalSupply` and `kLast` of the pair, needed to value its LP tokens.
+    pub async fn get_lp_state<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), AMMError<M>> {
+        let contract = self.contract(middleware);
+        let total_supply = contract.total_supply().call().await?;
+        let k_last = contract.k_last().call().await?;
+        Ok((total_supply, k_last))
+    }
+
+    /// Seconds since the reserves last changed as of `timestamp`, accounting for
+    /// `blockTimestampLast` wrapping around 2**32.
+    pub fn seconds_since_last_update(&self, timestamp: u64) -> u32 {
+        (timestamp as u32).wrapping_sub(self.block_timestamp_last)
+    }
+
+    /// Whether the reserves were fetched at most `max_age` blocks before `current_block`. Pools
+    /// whose fetch block is unknown are never fresh.
+    pub fn reserves_are_fresh(&self, current_block: u64, max_age: u64) -> bool {
+        self.block_number != 0 && current_block.saturating_sub(self.block_number) <= max_age
+    }
+
+    /// Adds an override setting the reserves of the pair to the reserves of `self`, so that
+    /// on-chain calls see the same state as [`UniswapV2Pool::simulate_swap`]. Token balances of
+    /// the pair are left untouched.
+    pub fn override_reserves(&self, state: &mut spoof::State) -> Result<(), StateOverrideError> {
+        state.account(self.address).store(
+            H256::from_low_u64_be(RESERVES_STORAGE_SLOT),
+            pack_reserves(self.reserve_0, self.reserve_1, self.block_timestamp_last)?,
+        );
+        Ok(())
+    }
+
+    /// Sets the reserves from a `Sync` log of the pair, emitted on every reserves update. The block
+    /// number of the log is recorded as the block the reserves were observed at.
+    ///
+    /// `block_timestamp_last` is left untouched as logs do not carry the block timestamp, callers
+    /// set it to the timestamp of the block of the log.
+    pub fn sync_from_log(&mut self, log: Log) -> Result<(), EventLogError> {
+        if log.topics.first() != Some(&SYNC_EVENT_SIGNATURE) {
+            return Err(EventLogError::InvalidEventSignature);
+        }
+        let block_number = log.block_number.map(|block_number| block_number.as_u64());
+        let sync_event = SyncFilter::decode_log(&RawLog::from(log))?;
+        self.reserve_0 = sync_event.reserve_0;
+        self.reserve_1 = sync_event.reserve_1;
+        if let Some(block_number) = block_number {
+            self.block_number = block_number;
+        }
+        Ok(())
+    }
+
     pub fn data_is_populated(&self) -> bool {
         !(self.token_a.is_zero()
             || self.token_b.is_zero()
@@ -144,6 +252,47 @@ impl UniswapV2Pool {
         numerator / denominator
     }
 
+    /// LP tokens that `_mintFee` would mint to `feeTo` on the next mint or burn, i.e. a sixth of
+    /// the growth in `sqrt(k)` since `kLast`. Zero when the protocol fee is off.
+    pub fn protocol_fee_liquidity(
+        &self,
+        total_supply: U256,
+        k_last: U256,
+        protocol_fee_on: bool,
+    ) -> U256 {
+        if !protocol_fee_on || k_last.is_zero() {
+            return U256::zero();
+        }
+        let root_k = (U256::from(self.reserve_0) * U256::from(self.reserve_1)).integer_sqrt();
+        let root_k_last = k_last.integer_sqrt();
+        if root_k <= root_k_last {
+            return U256::zero();
+        }
+        let numerator = total_supply * (root_k - root_k_last);
+        let denominator = root_k * U256::from(5) + root_k_last;
+        numerator / denominator
+    }
+
+    /// Amounts of token a and token b received for burning `liquidity` LP tokens, accounting for
+    /// the protocol fee liquidity minted ahead of the burn.
+    pub fn calculate_lp_token_amounts(
+        &self,
+        liquidity: U256,
+        total_supply: U256,
+        k_last: U256,
+        protocol_fee_on: bool,
+    ) -> (U256, U256) {
+        let total_supply =
+            total_supply + self.protocol_fee_liquidity(total_supply, k_last, protocol_fee_on);
+        if total_supply.is_zero() {
+            return (U256::zero(), U256::zero());
+        }
+        (
+            liquidity * U256::from(self.reserve_0) / total_supply,
+            liquidity * U256::from(self.reserve_1) / total_supply,
+        )
+    }
+
     pub fn calculate_price_64_x_64(&self, base_token: H160) -> Result<u128, ArithmeticError> {
         let decimal_shift = self.token_a_decimals as i8 - self.token_b_decimals as i8;
 
@@ -196,3 +345,102 @@ impl UniswapV2Pool {
             .encode_input(&input_tokens)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(reserve_0: u128, reserve_1: u128) -> UniswapV2Pool {
+        UniswapV2Pool::new(
+            H160::zero(),
+            H160::repeat_byte(1),
+            18,
+            H160::repeat_byte(2),
+            18,
+            reserve_0,
+            reserve_1,
+            300,
+        )
+    }
+
+    #[test]
+    fn test_sync_from_log() {
+        assert_eq!(SyncFilter::signature(), SYNC_EVENT_SIGNATURE);
+
+        let mut pool = pool(1000, 1000);
+        let log = Log {
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[Token::Uint(U256::from(7)), Token::Uint(U256::from(9))])
+                .into(),
+            block_number: Some(42.into()),
+            ..Default::default()
+        };
+        pool.sync_from_log(log).unwrap();
+
+        assert_eq!(
+            (pool.reserve_0, pool.reserve_1, pool.block_number),
+            (7, 9, 42)
+        );
+        assert!(pool.sync_from_log(Log::default()).is_err());
+    }
+
+    #[test]
+    fn test_pack_reserves() {
+        let packed = U256::from_big_endian(pack_reserves(1, 2, 3).unwrap().as_bytes());
+        assert_eq!(
+            packed,
+            U256::from(1) | U256::from(2) << 112 | U256::from(3) << 224
+        );
+
+        assert!(pack_reserves(1 << 112, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_reserves_staleness() {
+        let mut pool = pool(1000, 1000);
+        assert!(!pool.reserves_are_fresh(100, 10));
+
+        pool.block_number = 95;
+        assert!(pool.reserves_are_fresh(100, 10));
+        assert!(!pool.reserves_are_fresh(110, 10));
+
+        // blockTimestampLast wraps around 2**32
+        pool.block_timestamp_last = u32::MAX - 5;
+        assert_eq!(pool.seconds_since_last_update(1 << 32), 6);
+    }
+
+    #[test]
+    fn test_protocol_fee_liquidity() {
+        // sqrt(k) grew from 1000 to 1100, so a sixth of the 10% growth goes to the protocol
+        let pool = pool(1100, 1100);
+        let total_supply = U256::from(1_000_000);
+        let k_last = U256::from(1000 * 1000);
+
+        assert_eq!(
+            pool.protocol_fee_liquidity(total_supply, k_last, true),
+            U256::from(1_000_000 * 100 / (1100 * 5 + 1000))
+        );
+        assert!(pool
+            .protocol_fee_liquidity(total_supply, k_last, false)
+            .is_zero());
+        assert!(pool
+            .protocol_fee_liquidity(total_supply, U256::zero(), true)
+            .is_zero());
+    }
+
+    #[test]
+    fn test_calculate_lp_token_amounts() {
+        let pool = pool(1100, 2200);
+        let total_supply = U256::from(1_000_000);
+        let k_last = U256::from(1000 * 2000);
+
+        let (amount_0, amount_1) =
+            pool.calculate_lp_token_amounts(total_supply, total_supply, k_last, false);
+        assert_eq!((amount_0, amount_1), (U256::from(1100), U256::from(2200)));
+
+        let (amount_0, amount_1) =
+            pool.calculate_lp_token_amounts(total_supply, total_supply, k_last, true);
+        assert!(amount_0 < U256::from(1100));
+        assert!(amount_1 < U256::from(2200));
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use ethers::{
+    prelude::abigen,
+    providers::{Middleware, RawCall},
+    types::{spoof, BlockId, H160, U256},
+};
+
+use crate::errors::AMMError;
+
+abigen!(
+    IUniswapV2Router02,
+    r#"[
+        function getAmountsOut(uint256 amountIn, address[] memory path) external view returns (uint256[] memory amounts)
+    ]"#;
+);
+
+/// Quotes `amount_in` along `path` with the router `getAmountsOut`, against `state` overrides
+/// when set, e.g. reserves set with [`super::UniswapV2Pool::override_reserves`].
+pub async fn get_amounts_out<M: Middleware>(
+    router: H160,
+    amount_in: U256,
+    path: Vec<H160>,
+    middleware: Arc<M>,
+    block: Option<BlockId>,
+    state: Option<&spoof::State>,
+) -> Result<Vec<U256>, AMMError<M>> {
+    let mut get_amounts_out =
+        IUniswapV2Router02::new(router, middleware).get_amounts_out(amount_in, path);
+    if let Some(block) = block {
+        get_amounts_out = get_amounts_out.block(block);
+    }
+    let call = get_amounts_out.call_raw();
+    let amounts = match state {
+        Some(state) => call.state(state).await?,
+        None => call.await?,
+    };
+    Ok(amounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::uniswap_v2::UniswapV2Pool;
+    use ethers::providers::{Http, Provider};
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn test_get_amounts_out_with_overridden_reserves() {
+        dotenv::dotenv().ok();
+        let rpc_endpoint = std::env::var("NETWORK_RPC").expect("Missing NETWORK_RPC env variable");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+        let router = H160::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap();
+        let pool_address = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
+
+        let mut pool = UniswapV2Pool::new_from_address(pool_address, 300, middleware.clone())
+            .await
+            .unwrap();
+        pool.reserve_0 /= 2;
+        pool.reserve_1 *= 3;
+        let mut state = spoof::state();
+        pool.override_reserves(&mut state).unwrap();
+
+        let amount_in = U256::exp10(18);
+        let amounts = get_amounts_out(
+            router,
+            amount_in,
+            vec![pool.token_b, pool.token_a],
+            middleware,
+            None,
+            Some(&state),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            amounts[1],
+            pool.simulate_swap(pool.token_b, amount_in).unwrap()
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use ethers::{
+    abi::RawLog,
+    prelude::EthEvent,
+    providers::{Middleware, Provider, StreamExt, Ws},
+    types::{Filter, Log, H160},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::{SyncFilter, UniswapV2Pool, SYNC_EVENT_SIGNATURE};
+use crate::errors::{AMMError, EventLogError};
+
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+pub type SubscriptionHandle = JoinHandle<Result<(), AMMError<Provider<Ws>>>>;
+
+/// Reserves of a pool as set by a `Sync` log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveUpdate {
+    pub address: H160,
+    pub reserve_0: u128,
+    pub reserve_1: u128,
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl ReserveUpdate {
+    pub fn from_log(log: Log) -> Result<ReserveUpdate, EventLogError> {
+        if log.topics.first() != Some(&SYNC_EVENT_SIGNATURE) {
+            return Err(EventLogError::InvalidEventSignature);
+        }
+        let address = log.address;
+        let block_number = log
+            .block_number
+            .ok_or(EventLogError::LogBlockNumberNotFound)?
+            .as_u64();
+        let log_index = log.log_index.unwrap_or_default().as_u64();
+        let sync_event = SyncFilter::decode_log(&RawLog::from(log))?;
+        Ok(ReserveUpdate {
+            address,
+            reserve_0: sync_event.reserve_0,
+            reserve_1: sync_event.reserve_1,
+            block_number,
+            log_index,
+        })
+    }
+
+    /// Sets the reserves of `pool`, which must be the pool the update was emitted by.
+    /// `block_timestamp_last` is left untouched, see [`UniswapV2Pool::sync_from_log`].
+    pub fn apply(&self, pool: &mut UniswapV2Pool) {
+        pool.reserve_0 = self.reserve_0;
+        pool.reserve_1 = self.reserve_1;
+        pool.block_number = self.block_number;
+    }
+
+    fn position(&self) -> (u64, u64) {
+        (self.block_number, self.log_index)
+    }
+}
+
+/// Subscribes to the `Sync` logs of `pools` over the WebSocket endpoint `ws_url` and sends their
+/// reserve updates in chain order on the returned channel.
+///
+/// When the connection drops, the subscription is opened again after [`RECONNECT_DELAY`] and the
+/// logs emitted in the meantime are backfilled with `eth_getLogs` from the block of the last sent
+/// update, so no update is missed or sent twice. When `from_block` is set, the logs from that block
+/// onwards are backfilled on the first connection as well. The task gives up after
+/// `max_reconnects` consecutive failed connections, unlimited by default, and stops when the
+/// receiver is dropped. When a reorg removes logs that were sent, the logs of the blocks replacing
+/// them are sent as well, even at positions already sent. Updates removed without a replacement
+/// are not rolled back, use the `state_space` module for that.
+pub fn subscribe_sync_events(
+    ws_url: String,
+    pools: Vec<H160>,
+    from_block: Option<u64>,
+    max_reconnects: Option<usize>,
+) -> (mpsc::Receiver<ReserveUpdate>, SubscriptionHandle) {
+    let (sender, receiver) = mpsc::channel(1000);
+    let handle = tokio::spawn(async move {
+        let filter = Filter::new().address(pools).topic0(SYNC_EVENT_SIGNATURE);
+        let mut backfill_from = from_block;
+        let mut last_sent: Option<(u64, u64)> = None;
+        let mut failed_connections = 0;
+        loop {
+            let provider = match Provider::<Ws>::connect(ws_url.as_str()).await {
+                Ok(provider) => provider,
+                Err(err) => {
+                    failed_connections += 1;
+                    if max_reconnects.is_some_and(|max| failed_connections > max) {
+                        return Err(AMMError::ProviderError(err));
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            failed_connections = 0;
+
+            match follow_sync_events(&provider, &filter, backfill_from, &sender, &mut last_sent)
+                .await
+            {
+                Ok(true) | Err(AMMError::ProviderError(_)) => {}
+                Ok(false) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+            backfill_from = last_sent
+                .map(|(block_number, _)| block_number)
+                .or(backfill_from);
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    });
+    (receiver, handle)
+}
+
+/// Sends the updates of one connection until it drops, returning false once the receiver is
+/// dropped.
+async fn follow_sync_events(
+    provider: &Provider<Ws>,
+    filter: &Filter,
+    backfill_from: Option<u64>,
+    sender: &mpsc::Sender<ReserveUpdate>,
+    last_sent: &mut Option<(u64, u64)>,
+) -> Result<bool, AMMError<Provider<Ws>>> {
+    // Subscribing before backfilling leaves no gap between the two, logs received twice are
+    // dropped by comparing their position with the last sent update.
+    let mut stream = provider.subscribe_logs(filter).await?;
+    if let Some(from_block) = backfill_from {
+        let logs = provider
+            .get_logs(&filter.clone().from_block(from_block))
+            .await?;
+        for log in logs {
+            if !send_update(log, sender, last_sent).await? {
+                return Ok(false);
+            }
+        }
+    }
+    while let Some(log) = stream.next().await {
+        if !send_update(log, sender, last_sent).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Sends the update of `log` unless it was already sent, returning false once the receiver is
+/// dropped. A removed log moves `last_sent` back before its position, so that the logs of the
+/// blocks that replaced it are sent.
+async fn send_update(
+    log: Log,
+    sender: &mpsc::Sender<ReserveUpdate>,
+    last_sent: &mut Option<(u64, u64)>,
+) -> Result<bool, EventLogError> {
+    let removed = log.removed == Some(true);
+    let update = ReserveUpdate::from_log(log)?;
+    if removed {
+        if last_sent.is_some_and(|last_sent| update.position() <= last_sent) {
+            *last_sent = match update.position() {
+                (block_number, 0) => block_number.checked_sub(1).map(|block| (block, u64::MAX)),
+                (block_number, log_index) => Some((block_number, log_index - 1)),
+            };
+        }
+        return Ok(true);
+    }
+    if last_sent.is_some_and(|last_sent| update.position() <= last_sent) {
+        return Ok(true);
+    }
+    *last_sent = Some(update.position());
+    Ok(sender.send(update).await.is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, Token},
+        types::U256,
+    };
+
+    use super::*;
+
+    fn sync_log(block_number: u64, log_index: u64) -> Log {
+        Log {
+            address: H160::from_low_u64_be(1),
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: encode(&[
+                Token::Uint(U256::from(block_number)),
+                Token::Uint(U256::from(log_index)),
+            ])
+            .into(),
+            block_number: Some(block_number.into()),
+            log_index: Some(log_index.into()),
+            ..Default::default()
+        }
+    }
+
+    fn removed(log: Log) -> Log {
+        Log {
+            removed: Some(true),
+            ..log
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_update_skips_sent_logs() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let mut last_sent = None;
+        // A backfilled log followed by the same log and an older one from the live stream.
+        for log in [
+            sync_log(10, 2),
+            sync_log(10, 2),
+            sync_log(10, 1),
+            sync_log(11, 0),
+        ] {
+            assert!(send_update(log, &sender, &mut last_sent).await.unwrap());
+        }
+        drop(sender);
+
+        let mut updates = vec![];
+        while let Some(update) = receiver.recv().await {
+            updates.push((update.block_number, update.log_index));
+        }
+        assert_eq!(updates, vec![(10, 2), (11, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_send_update_after_reorg() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let mut last_sent = None;
+        // Block 11 is replaced by a block with a single log, which reuses a sent position
+        for log in [
+            sync_log(10, 0),
+            sync_log(11, 0),
+            sync_log(11, 1),
+            removed(sync_log(11, 0)),
+            removed(sync_log(11, 1)),
+            sync_log(11, 0),
+            sync_log(12, 0),
+        ] {
+            assert!(send_update(log, &sender, &mut last_sent).await.unwrap());
+        }
+        drop(sender);
+
+        let mut updates = vec![];
+        while let Some(update) = receiver.recv().await {
+            updates.push((update.block_number, update.log_index));
+        }
+        assert_eq!(updates, vec![(10, 0), (11, 0), (11, 1), (11, 0), (12, 0)]);
+    }
+}
@@ -1,84 +1,160 @@
-use std::{fs::read_to_string, sync::Arc};
+use std::{collections::HashSet, sync::Arc};
 
-use super::{factory::UniswapV2Factory, UniswapV2Pool};
+pub use super::checkpoint::Checkpoint;
+use super::{
+    batch_request::{refresh_reserves, RejectReason},
+    checkpoint::{CheckpointKey, CheckpointStore},
+    factory::UniswapV2Factory,
+    UniswapV2Pool,
+};
 use crate::errors::{AMMError, CheckpointError};
-use ethers::providers::Middleware;
-use serde::{Deserialize, Serialize};
+use ethers::{providers::Middleware, types::H160};
+use futures::future;
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Checkpoint {
-    pub timestamp: usize,
-    pub block_number: u64,
-    pub factory: UniswapV2Factory,
-    pub pools: Vec<UniswapV2Pool>,
+/// Syncs all the factory pools, returning the valid pools along with the pools rejected by the
+/// pool data batch request. The factory checkpoint in `store` is resumed from when there is one,
+/// and updated. Also returns the checkpoint pools whose reserves could not be refreshed, which
+/// keep the reserves and block number of the checkpoint.
+pub async fn sync_uniswap_v2_pools<M: Middleware, S: CheckpointStore>(
+    factory: UniswapV2Factory,
+    middleware: Arc<M>,
+    store: &S,
+) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>, Vec<H160>), AMMError<M>> {
+    sync_uniswap_v2_pools_with_checkpoint(factory, middleware, store).await
 }
 
-impl Checkpoint {
-    pub fn new(
-        timestamp: usize,
-        block_number: u64,
-        factory: UniswapV2Factory,
-        pools: Vec<UniswapV2Pool>,
-    ) -> Checkpoint {
-        Checkpoint {
-            timestamp,
-            block_number,
-            factory,
-            pools,
-        }
-    }
+/// Pools contributed by a single factory to [`sync_uniswap_v2_pools_from_factories`].
+#[derive(Debug, Clone)]
+pub struct FactorySyncSummary {
+    pub factory: H160,
+    pub pools: usize,
+    pub duplicates: usize,
+    pub rejected: usize,
+    /// Why the factory could not be synced, in which case it contributed no pools.
+    pub error: Option<String>,
+}
 
-    pub fn read_from_path(path: &str) -> Result<Checkpoint, CheckpointError> {
-        let path = format!("checkpoint_data/{}", path);
-        let checkpoint: Checkpoint = serde_json::from_str(read_to_string(path)?.as_str())?;
-        Ok(checkpoint)
-    }
+#[derive(Debug, Clone)]
+pub struct MultiFactorySync {
+    pub pools: Vec<UniswapV2Pool>,
+    pub rejected: Vec<(H160, RejectReason)>,
+    /// Pools whose reserves could not be refreshed, see [`sync_uniswap_v2_pools`].
+    pub stale: Vec<H160>,
+    pub summaries: Vec<FactorySyncSummary>,
+}
+
+/// Syncs the pools of every factory concurrently, each against its own checkpoint in `store`, and
+/// merges them into one set. Pools are deduplicated by address, the first factory in `factories`
+/// wins. A factory that fails to sync does not stop the others, its error is in its summary.
+pub async fn sync_uniswap_v2_pools_from_factories<M: Middleware, S: CheckpointStore>(
+    factories: Vec<UniswapV2Factory>,
+    middleware: Arc<M>,
+    store: &S,
+) -> MultiFactorySync {
+    let futures = factories.iter().map(|factory| {
+        sync_uniswap_v2_pools_with_checkpoint(factory.clone(), middleware.clone(), store)
+    });
+    let results = future::join_all(futures).await;
 
-    pub fn save_to_path(&self, path: &str) -> Result<(), CheckpointError> {
-        let path = format!("checkpoint_data/{}", path);
-        std::fs::write(path, serde_json::to_string_pretty(&self)?)?;
-        Ok(())
+    let mut seen = HashSet::new();
+    let mut merged = MultiFactorySync {
+        pools: vec![],
+        rejected: vec![],
+        stale: vec![],
+        summaries: vec![],
+    };
+    for (factory, result) in factories.iter().zip(results) {
+        let mut summary = FactorySyncSummary {
+            factory: factory.address,
+            pools: 0,
+            duplicates: 0,
+            rejected: 0,
+            error: None,
+        };
+        let (pools, mut rejected, mut stale) = match result {
+            Ok(synced) => synced,
+            Err(err) => {
+                summary.error = Some(format!("{:?}", err));
+                merged.summaries.push(summary);
+                continue;
+            }
+        };
+        summary.rejected = rejected.len();
+        for pool in pools {
+            if seen.insert(pool.address) {
+                summary.pools += 1;
+                merged.pools.push(pool);
+            } else {
+                summary.duplicates += 1;
+            }
+        }
+        merged.rejected.append(&mut rejected);
+        merged.stale.append(&mut stale);
+        merged.summaries.push(summary);
     }
+    merged
 }
 
-pub async fn sync_uniswap_v2_pools<M: Middleware>(
+async fn sync_uniswap_v2_pools_with_checkpoint<M: Middleware, S: CheckpointStore>(
     factory: UniswapV2Factory,
     middleware: Arc<M>,
-) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
-    let checkpoint = Checkpoint::read_from_path("uniswap_v2_pairs");
-    let pools = match checkpoint {
-        Ok(_) => sync_uniswap_v2_pools_from_checkpoint(factory, middleware).await?,
-        _ => sync_uniswap_v2_pools_no_checkpoint(factory, middleware).await?,
-    };
-    Ok(pools)
+    store: &S,
+) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>, Vec<H160>), AMMError<M>> {
+    let key = CheckpointKey::for_factory(&factory, middleware.as_ref()).await?;
+    match store.load(&key)? {
+        Some(checkpoint) => {
+            sync_uniswap_v2_pools_from_checkpoint(checkpoint, middleware, store, &key).await
+        }
+        None => sync_uniswap_v2_pools_no_checkpoint(factory, middleware, store, &key).await,
+    }
 }
 
-async fn sync_uniswap_v2_pools_no_checkpoint<M: Middleware>(
+async fn sync_uniswap_v2_pools_no_checkpoint<M: Middleware, S: CheckpointStore>(
     factory: UniswapV2Factory,
     middleware: Arc<M>,
-) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
-    let (pools, block_number) = factory.get_all_pools(middleware, None).await?;
-    Checkpoint::new(
+    store: &S,
+    key: &CheckpointKey,
+) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>, Vec<H160>), AMMError<M>> {
+    let (pools, rejected, block_number) = factory.get_all_pools(middleware, None, None).await?;
+    let checkpoint = Checkpoint::new(
         chrono::Utc::now().timestamp() as usize,
         block_number,
         factory,
-        pools.clone(),
-    )
-    .save_to_path("uniswap_v2_pairs")?;
-    Ok(pools)
+        pools,
+        dedup_rejected(rejected),
+    );
+    store.save(key, &checkpoint)?;
+    Ok((checkpoint.pools, checkpoint.rejected, vec![]))
 }
 
-async fn sync_uniswap_v2_pools_from_checkpoint<M: Middleware>(
-    factory: UniswapV2Factory,
+async fn sync_uniswap_v2_pools_from_checkpoint<M: Middleware, S: CheckpointStore>(
+    mut checkpoint: Checkpoint,
     middleware: Arc<M>,
-) -> Result<Vec<UniswapV2Pool>, AMMError<M>> {
-    let mut checkpoint = Checkpoint::read_from_path("uniswap_v2_pairs")?;
+    store: &S,
+    key: &CheckpointKey,
+) -> Result<(Vec<UniswapV2Pool>, Vec<(H160, RejectReason)>, Vec<H160>), AMMError<M>> {
+    if checkpoint.factory.address != key.factory {
+        return Err(CheckpointError::FactoryMismatch {
+            expected: key.factory,
+            found: checkpoint.factory.address,
+        }
+        .into());
+    }
     let end_block = middleware
         .get_block_number()
         .await
         .map_err(AMMError::MiddlewareError)?
         .as_u64();
-    let mut new_pools = factory
+    // Stored reserves are as old as the checkpoint, bring them to the block the new pools are
+    // read at. Pools whose reserves cannot be fetched keep their old reserves and block number.
+    let stale = refresh_reserves(
+        &mut checkpoint.pools,
+        middleware.clone(),
+        Some(end_block.into()),
+    )
+    .await?;
+    let (mut new_pools, mut new_rejected, _) = checkpoint
+        .factory
         .get_pools_from_logs(
             middleware,
             Some(checkpoint.block_number + 1),
@@ -87,8 +163,45 @@ async fn sync_uniswap_v2_pools_from_checkpoint<M: Middleware>(
         )
         .await?;
     checkpoint.pools.append(&mut new_pools);
+    checkpoint.rejected.append(&mut new_rejected);
+    checkpoint.rejected = dedup_rejected(checkpoint.rejected);
     checkpoint.block_number = end_block;
     checkpoint.timestamp = chrono::Utc::now().timestamp() as usize;
-    checkpoint.save_to_path("uniswap_v2_pairs")?;
-    Ok(checkpoint.pools)
+    store.save(key, &checkpoint)?;
+    Ok((checkpoint.pools, checkpoint.rejected, stale))
+}
+
+/// Keeps the latest reason each pool was rejected for, a pool rejected again when the blocks it
+/// was created in are synced again is only stored once.
+fn dedup_rejected(rejected: Vec<(H160, RejectReason)>) -> Vec<(H160, RejectReason)> {
+    let mut seen = HashSet::new();
+    let mut deduped: Vec<_> = rejected
+        .into_iter()
+        .rev()
+        .filter(|(address, _)| seen.insert(*address))
+        .collect();
+    deduped.reverse();
+    deduped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_rejected() {
+        let (a, b) = (H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+        let rejected = vec![
+            (a, RejectReason::MissingPoolCode),
+            (b, RejectReason::MissingPoolCode),
+            (a, RejectReason::PoolCallFailed),
+        ];
+        assert_eq!(
+            dedup_rejected(rejected),
+            vec![
+                (b, RejectReason::MissingPoolCode),
+                (a, RejectReason::PoolCallFailed)
+            ]
+        );
+    }
 }